@@ -0,0 +1,92 @@
+use crate::audio::AudioEngine;
+use crate::battery::BatterySource;
+use crate::config::Config;
+use crate::shutdown::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// a looping, louder-each-time alarm for the critical level, stopped by
+// plugging in, acknowledging, shutdown or after alarm_max_duration seconds
+#[derive(Clone, Default)]
+pub struct Alarm {
+    acknowledged: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+}
+
+impl Alarm {
+    pub fn new() -> Alarm {
+        Alarm {
+            acknowledged: Arc::new(AtomicBool::new(false)),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // setting this flag (signal, control socket, notification) stops the alarm
+    pub fn acknowledge_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.acknowledged)
+    }
+
+    pub fn acknowledge(&self) -> bool {
+        self.acknowledged.store(true, Ordering::Relaxed);
+        self.is_running()
+    }
+
+    // stays set after the alarm stopped until the next one starts
+    pub fn is_acknowledged(&self) -> bool {
+        self.acknowledged.load(Ordering::Relaxed)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn start(
+        &self,
+        configuration: &Config,
+        source: &Arc<dyn BatterySource>,
+        audio: &AudioEngine,
+        shutdown: &Shutdown,
+    ) {
+        if self.running.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.acknowledged.store(false, Ordering::Relaxed);
+        let alarm = self.clone();
+        let audio = audio.clone();
+        let shutdown = shutdown.clone();
+        let mut sound = configuration.sound("alarm");
+        let source = Arc::clone(source);
+        let max_duration = Duration::from_secs(configuration.alarm_max_duration);
+        let step = configuration.alarm_volume_step;
+        let max_volume = configuration.alarm_max_volume;
+        thread::spawn(move || {
+            println!("alarm: started");
+            let started = Instant::now();
+            let reason = loop {
+                if alarm.acknowledged.load(Ordering::Relaxed) {
+                    break "acknowledged";
+                }
+                if shutdown.is_triggered() {
+                    break "shutting down";
+                }
+                // a read error stops it too, better than ringing for nothing
+                if source.status().as_deref() != Ok("Discharging") {
+                    break "charger connected";
+                }
+                if started.elapsed() >= max_duration {
+                    break "maximum duration reached";
+                }
+                if !audio.play_and_wait(&sound) {
+                    // nothing to play, waiting keeps the loop from spinning
+                    shutdown.sleep(Duration::from_secs(1));
+                }
+                sound.volume = (sound.volume + step).min(max_volume);
+                shutdown.sleep(Duration::from_millis(sound.gap));
+            };
+            println!("alarm: stopped, {}", reason);
+            alarm.running.store(false, Ordering::Relaxed);
+        });
+    }
+}
@@ -0,0 +1,386 @@
+use crate::alarm::Alarm;
+use crate::audio::AudioEngine;
+use crate::battery::BatterySource;
+use crate::clock::Clock;
+use crate::config::{Config, SoundConfig};
+use crate::drain::DrainReport;
+use crate::estimate::format_duration;
+use crate::history::History;
+use crate::hooks;
+use crate::monitor::*;
+use crate::notify::*;
+use crate::quiet::QuietHours;
+use crate::shutdown::Shutdown;
+use crate::snooze::Snooze;
+use crate::systemd;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+// everything the Alerter does to the outside world, the daemon really does
+// it while --simulate only prints what would have happened
+pub trait Effects {
+    fn log(&self, line: &str);
+    // the one line status after every poll, STATUS= under systemd
+    fn status(&self, line: &str);
+    fn notify(&self, message: String, progress: u64, urgency: &str);
+    // `on_click` runs once the action button was clicked
+    fn notify_action(
+        &self,
+        message: String,
+        label: &str,
+        urgency: &str,
+        on_click: Box<dyn FnOnce() + Send>,
+    );
+    // `event` is the sound event name ("critical", "plug_in", ...)
+    fn play(&self, event: &str, sound: &SoundConfig);
+    fn run_hook(&self, command: &str, env: &[(String, String)]);
+    fn start_alarm(&self, configuration: &Config);
+    fn alarm_running(&self) -> bool;
+}
+
+pub struct DaemonEffects {
+    audio: AudioEngine,
+    alarm: Alarm,
+    source: Arc<dyn BatterySource>,
+    shutdown: Shutdown,
+}
+
+impl DaemonEffects {
+    // `source` is what the alarm watches for the charger
+    pub fn new(
+        audio: &AudioEngine,
+        alarm: &Alarm,
+        source: &Arc<dyn BatterySource>,
+        shutdown: &Shutdown,
+    ) -> DaemonEffects {
+        DaemonEffects {
+            audio: audio.clone(),
+            alarm: alarm.clone(),
+            source: Arc::clone(source),
+            shutdown: shutdown.clone(),
+        }
+    }
+}
+
+impl Effects for DaemonEffects {
+    fn log(&self, line: &str) {
+        println!("{}", line);
+    }
+
+    fn status(&self, line: &str) {
+        systemd::notify(&format!("STATUS={}", line));
+    }
+
+    fn notify(&self, message: String, progress: u64, urgency: &str) {
+        spawn_notif(message, progress, urgency);
+    }
+
+    fn notify_action(
+        &self,
+        message: String,
+        label: &str,
+        urgency: &str,
+        on_click: Box<dyn FnOnce() + Send>,
+    ) {
+        // waiting for the action blocks, so it gets its own thread
+        let label = label.to_string();
+        let urgency = urgency.to_string();
+        thread::spawn(move || {
+            if spawn_notif_action(message, &label, &urgency) {
+                on_click();
+            }
+        });
+    }
+
+    fn play(&self, _event: &str, sound: &SoundConfig) {
+        self.audio.play(sound);
+    }
+
+    fn run_hook(&self, command: &str, env: &[(String, String)]) {
+        hooks::run_hook(command, env);
+    }
+
+    fn start_alarm(&self, configuration: &Config) {
+        self.alarm
+            .start(configuration, &self.source, &self.audio, &self.shutdown);
+        let ack = self.alarm.acknowledge_flag();
+        self.notify_action(
+            "Battery is critical, plug in the charger or acknowledge to stop the alarm."
+                .to_string(),
+            "Acknowledge",
+            "critical",
+            Box::new(move || ack.store(true, Ordering::Relaxed)),
+        );
+    }
+
+    fn alarm_running(&self) -> bool {
+        self.alarm.is_running()
+    }
+}
+
+// turns monitor events into notifications, sounds and hook commands
+pub struct Alerter {
+    configuration: Config,
+    effects: Box<dyn Effects>,
+    allow_execute: bool,
+    low_sound_played: bool,
+    history: Option<History>,
+    // the alarm only starts once per critical episode
+    alarm_armed: bool,
+    quiet: QuietHours,
+    // snoozed or paused reminders skip the low and critical notifications,
+    // sounds and alarm, near dead warnings and hooks still go out
+    snooze: Snooze,
+    clock: Arc<dyn Clock>,
+}
+
+impl Alerter {
+    pub fn new(
+        configuration: &Config,
+        effects: Box<dyn Effects>,
+        clock: Arc<dyn Clock>,
+    ) -> Alerter {
+        Alerter {
+            configuration: configuration.clone(),
+            effects,
+            allow_execute: true,
+            low_sound_played: false,
+            history: match configuration.history_enable {
+                true => match History::new(configuration) {
+                    Ok(h) => Some(h),
+                    Err(e) => {
+                        println!("history: {}, not recording", e);
+                        None
+                    }
+                },
+                false => None,
+            },
+            alarm_armed: true,
+            quiet: QuietHours::with_clock(configuration, Arc::clone(&clock)),
+            snooze: Snooze::new(),
+            clock,
+        }
+    }
+
+    pub fn with_snooze(mut self, snooze: &Snooze) -> Alerter {
+        self.snooze = snooze.clone();
+        self
+    }
+
+    fn snoozed(&self) -> bool {
+        self.snooze.is_active(self.clock.now())
+    }
+
+    pub fn handle(&mut self, event: Event) {
+        match event {
+            Event::Polled {
+                reading,
+                level,
+                smoothed,
+            } => self.polled(&reading, level, smoothed),
+            Event::LevelCrossed { from, to, .. } => {
+                self.effects
+                    .log(&format!("level : {} -> {}", from.as_str(), to.as_str()));
+            }
+            Event::PluggedIn(reading) => {
+                self.effects.run_hook(
+                    &self.configuration.plug_in_check_command_to_exec,
+                    &reading.hook_env(),
+                );
+                if self.quiet.allows_sound("plug") && !self.snoozed() {
+                    self.effects
+                        .play("plug_in", &self.configuration.sound("plug_in"));
+                }
+            }
+            Event::PluggedOut(reading) => {
+                self.effects.run_hook(
+                    &self.configuration.plug_out_check_command_to_exec,
+                    &reading.hook_env(),
+                );
+                if self.quiet.allows_sound("plug") && !self.snoozed() {
+                    self.effects
+                        .play("plug_out", &self.configuration.sound("plug_out"));
+                }
+            }
+            Event::ReadError(e) => self
+                .effects
+                .log(&format!("Failed to read the battery : {}", e)),
+            Event::DrainSpike(_) | Event::HealthDropped(_) | Event::PeripheralLow(_)
+                if self.snoozed() =>
+            {
+                self.effects.log("reminders are snoozed, not notifying");
+            }
+            Event::DrainSpike(report) => self.drain(report),
+            Event::HealthDropped(level) => self.effects.notify(
+                format!(
+                    "Battery health dropped below {}% of its design capacity.",
+                    level
+                ),
+                0,
+                self.quiet.urgency("health"),
+            ),
+            Event::PeripheralLow(alert) => {
+                self.effects.log(&alert.message);
+                self.effects.notify(
+                    alert.message,
+                    alert.capacity.unwrap_or(0),
+                    self.quiet.urgency("peripheral"),
+                );
+            }
+        }
+    }
+
+    fn polled(&mut self, reading: &Reading, level: Level, smoothed: Option<u64>) {
+        let batt_capacity = reading.capacity();
+        let time_left = reading.time_left();
+        if let Some(h) = &mut self.history {
+            h.record(&reading.sample);
+        }
+        self.effects.status(&format!(
+            "{}% {}{}",
+            batt_capacity,
+            reading.status(),
+            time_left
+        ));
+        let env = reading.hook_env();
+        let snoozed = self.snoozed();
+        let configuration = &self.configuration;
+        let effects = &self.effects;
+        match reading.status() {
+            "Charging" => {
+                self.alarm_armed = true;
+                effects.log("Battery is Charging");
+                effects.log(&format!("Batt level {}{}", batt_capacity, time_left));
+                return;
+            }
+            "Full" => {
+                effects.log("Battery is Full");
+                return;
+            }
+            "Discharging" => {}
+            _ => {
+                effects.log("Unknown.");
+                return;
+            }
+        }
+        effects.log("Battery is Discharging");
+        effects.log(&format!("Batt level {}{}", batt_capacity, time_left));
+        effects.log(&format!("allow execute : {}", self.allow_execute));
+        if let Some(secs) = smoothed {
+            effects.log(&format!(
+                "smoothed time remaining : {} ({})",
+                format_duration(secs),
+                level.as_str()
+            ));
+        }
+        match level {
+            Level::Critical => {
+                let c_exec_crit = &configuration.bat_crit_command_to_exec;
+                if self.allow_execute && !c_exec_crit.is_empty() {
+                    effects.run_hook(c_exec_crit, &env);
+                    self.allow_execute = false;
+                    effects.log(&format!("set allow execute to : {}", self.allow_execute));
+                }
+                if snoozed {
+                    effects.log("reminders are snoozed");
+                    return;
+                }
+                effects.notify(
+                    format!("{batt_capacity}% Battery remaining{time_left}, please plug in the charger."),
+                    batt_capacity,
+                    self.quiet.urgency("critical"),
+                );
+                let critical_sound = self.quiet.allows_sound("critical");
+                if configuration.alarm_enable && self.alarm_armed && critical_sound {
+                    self.alarm_armed = false;
+                    effects.start_alarm(configuration);
+                }
+                if !effects.alarm_running() && critical_sound {
+                    effects.play("critical", &configuration.sound("critical"));
+                }
+            }
+            Level::Low => {
+                // the low sound still plays once the snooze is over
+                if !self.low_sound_played && !snoozed {
+                    if self.quiet.allows_sound("low") {
+                        effects.play("low", &configuration.sound("low"));
+                    }
+                    self.low_sound_played = true;
+                }
+                let c_exec_low = &configuration.bat_low_command_to_exec;
+                if self.allow_execute && !c_exec_low.is_empty() {
+                    effects.run_hook(c_exec_low, &env);
+                    self.allow_execute = false;
+                    effects.log(&format!("set allow execute to : {}", self.allow_execute));
+                }
+            }
+            Level::NearDead => {
+                let c_near_ded = &configuration.near_ded_command;
+                effects.notify(
+                    format!(
+                        "Battery is less than {}% The system will run {} in 15 seconds from now...",
+                        batt_capacity, c_near_ded
+                    ),
+                    0,
+                    self.quiet.urgency("near_dead"),
+                );
+                effects.run_hook(c_near_ded, &env);
+            }
+            Level::Normal => {
+                effects.log(&format!("Batt level {}", batt_capacity));
+                self.allow_execute = true;
+                self.low_sound_played = false;
+                self.alarm_armed = true;
+                effects.log(&format!("set allow execute to : {}", self.allow_execute));
+            }
+        }
+    }
+
+    fn drain(&self, report: DrainReport) {
+        let configuration = &self.configuration;
+        let urgency = self.quiet.urgency("drain");
+        let names: Vec<String> = report
+            .top
+            .iter()
+            .map(|p| format!("{} ({})", p.name, p.pid))
+            .collect();
+        let mut message = format!(
+            "Battery is draining fast: {:.1}%/h, usually {:.1}%/h.",
+            report.rate, report.baseline
+        );
+        if !names.is_empty() {
+            message.push_str(&format!("\nTop CPU users: {}", names.join(", ")));
+        }
+        self.effects.log(&message);
+        let signal = match &configuration.drain_action[..] {
+            "kill" => "TERM",
+            "stop" => "STOP",
+            _ => {
+                self.effects.notify(message, 0, urgency);
+                return;
+            }
+        };
+        let target = match report.top.first() {
+            Some(p) => (p.pid, p.name.clone()),
+            None => {
+                self.effects.notify(message, 0, urgency);
+                return;
+            }
+        };
+        let label = format!("{} {}", configuration.drain_action, target.1);
+        self.effects.notify_action(
+            message,
+            &label,
+            urgency,
+            Box::new(move || {
+                println!("sending SIG{} to {} ({})", signal, target.1, target.0);
+                hooks::spawn_process(
+                    "kill",
+                    vec![&format!("-{}", signal), &target.0.to_string()],
+                    &[],
+                );
+            }),
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::builtin_sounds;
+use crate::config::SoundConfig;
+use crate::shutdown::Shutdown;
+use rodio::buffer::SamplesBuffer;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// a fully decoded sound, kept in memory so repeated alerts skip decoding
+#[derive(Clone)]
+pub struct Sound {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Arc<Vec<i16>>,
+}
+
+impl Sound {
+    pub fn decode(path: &str) -> Result<Sound, String> {
+        let file = fs::File::open(path).map_err(|e| format!("{} : {}", path, e))?;
+        let decoder =
+            Decoder::new(BufReader::new(file)).map_err(|e| format!("{} : {}", path, e))?;
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        let samples: Vec<i16> = decoder.collect();
+        Ok(Sound {
+            channels,
+            sample_rate,
+            samples: Arc::new(samples),
+        })
+    }
+
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() as u64 / self.channels.max(1) as u64;
+        Duration::from_millis(frames * 1000 / self.sample_rate.max(1) as u64)
+    }
+
+    pub fn source(&self) -> SamplesBuffer<i16> {
+        SamplesBuffer::new(self.channels, self.sample_rate, self.samples.to_vec())
+    }
+}
+
+// volume is a factor (1.0 plays the file as is), `play` returns once the
+// sound has finished or `shutdown` was triggered
+pub trait AudioOutput {
+    fn play(
+        &mut self,
+        sound: &Sound,
+        volume: f32,
+        fade_in: Duration,
+        shutdown: &Shutdown,
+    ) -> Result<(), String>;
+}
+
+// the default output device through rodio, opened lazily so a missing device
+// (no sound card, pipewire not running yet) is retried on the next alert
+#[derive(Default)]
+pub struct RodioOutput {
+    stream: Option<(OutputStream, OutputStreamHandle)>,
+}
+
+impl RodioOutput {
+    pub fn new() -> RodioOutput {
+        RodioOutput { stream: None }
+    }
+}
+
+impl AudioOutput for RodioOutput {
+    fn play(
+        &mut self,
+        sound: &Sound,
+        volume: f32,
+        fade_in: Duration,
+        shutdown: &Shutdown,
+    ) -> Result<(), String> {
+        if self.stream.is_none() {
+            match OutputStream::try_default() {
+                Ok(s) => self.stream = Some(s),
+                Err(e) => return Err(format!("no audio output device : {}", e)),
+            }
+        }
+        let handle = match &self.stream {
+            Some((_, handle)) => handle,
+            None => return Err("no audio output device".to_string()),
+        };
+        let sink = match Sink::try_new(handle) {
+            Ok(sink) => sink,
+            Err(e) => {
+                // the device went away, reopen it next time
+                self.stream = None;
+                return Err(format!("failed to open the audio sink : {}", e));
+            }
+        };
+        sink.append(sound.source().amplify(volume).fade_in(fade_in));
+        // polled rather than sleep_until_end so shutdown cuts it short
+        while !sink.empty() {
+            if !shutdown.sleep(Duration::from_millis(50)) {
+                sink.stop();
+                return Err("interrupted by shutdown".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// discards every sound, for machines without audio and for testing
+pub struct NullOutput;
+
+impl AudioOutput for NullOutput {
+    fn play(
+        &mut self,
+        sound: &Sound,
+        volume: f32,
+        _fade_in: Duration,
+        _shutdown: &Shutdown,
+    ) -> Result<(), String> {
+        println!(
+            "audio: null output, skipping {:.1}s of sound at volume {:.2}",
+            sound.duration().as_secs_f32(),
+            volume
+        );
+        Ok(())
+    }
+}
+
+pub enum AudioCommand {
+    // the optional sender is told whether the sound played once it finished
+    Play(SoundConfig, Option<Sender<bool>>),
+}
+
+// handle to the audio thread, cheap to clone into every thread that beeps
+#[derive(Clone)]
+pub struct AudioEngine {
+    sender: Sender<AudioCommand>,
+}
+
+impl AudioEngine {
+    // a sound still playing when `shutdown` is triggered stops at once
+    pub fn new(backend: &str, shutdown: &Shutdown) -> AudioEngine {
+        match backend {
+            "null" => AudioEngine::with_output(|| Box::new(NullOutput), shutdown),
+            _ => AudioEngine::with_output(|| Box::new(RodioOutput::new()), shutdown),
+        }
+    }
+
+    // the output is created on the audio thread since rodio streams are not Send
+    pub fn with_output<F>(make_output: F, shutdown: &Shutdown) -> AudioEngine
+    where
+        F: FnOnce() -> Box<dyn AudioOutput> + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let shutdown = shutdown.clone();
+        thread::spawn(move || audio_thread(make_output(), receiver, shutdown));
+        AudioEngine { sender }
+    }
+
+    // queue a sound, a path of "none" means silent
+    pub fn play(&self, sound: &SoundConfig) {
+        if sound.path == "none" || sound.repeat == 0 {
+            return;
+        }
+        if self
+            .sender
+            .send(AudioCommand::Play(sound.clone(), None))
+            .is_err()
+        {
+            println!("audio: the audio thread is gone");
+        }
+    }
+
+    // like play but returns once the sound finished, false if nothing played
+    pub fn play_and_wait(&self, sound: &SoundConfig) -> bool {
+        if sound.path == "none" || sound.repeat == 0 {
+            return false;
+        }
+        let (done, finished) = channel();
+        if self
+            .sender
+            .send(AudioCommand::Play(sound.clone(), Some(done)))
+            .is_err()
+        {
+            println!("audio: the audio thread is gone");
+            return false;
+        }
+        finished.recv().unwrap_or(false)
+    }
+}
+
+struct CachedSound {
+    modified: Option<SystemTime>,
+    sound: Sound,
+}
+
+fn load_sound(cache: &mut HashMap<String, CachedSound>, path: &str) -> Result<Sound, String> {
+    if let Some(name) = path.strip_prefix("builtin:") {
+        if let Some(cached) = cache.get(path) {
+            return Ok(cached.sound.clone());
+        }
+        let sound = builtin_sounds::load(name)?;
+        cache.insert(
+            path.to_string(),
+            CachedSound {
+                modified: None,
+                sound: sound.clone(),
+            },
+        );
+        return Ok(sound);
+    }
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    if !Path::new(path).is_file() {
+        return Err(format!("{} is not a file", path));
+    }
+    if let Some(cached) = cache.get(path) {
+        if cached.modified == modified {
+            return Ok(cached.sound.clone());
+        }
+    }
+    let sound = Sound::decode(path)?;
+    cache.insert(
+        path.to_string(),
+        CachedSound {
+            modified,
+            sound: sound.clone(),
+        },
+    );
+    Ok(sound)
+}
+
+fn audio_thread(
+    mut output: Box<dyn AudioOutput>,
+    receiver: Receiver<AudioCommand>,
+    shutdown: Shutdown,
+) {
+    let mut cache: HashMap<String, CachedSound> = HashMap::new();
+    for command in receiver {
+        match command {
+            AudioCommand::Play(settings, done) => {
+                let sound = match load_sound(&mut cache, &settings.path) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("Audio Cant be played : {}", e);
+                        if let Some(done) = done {
+                            let _ = done.send(false);
+                        }
+                        continue;
+                    }
+                };
+                let mut played = true;
+                let volume = settings.volume as f32 / 100.0;
+                let fade_in = Duration::from_millis(settings.fade_in);
+                for i in 0..settings.repeat {
+                    if i > 0 && !shutdown.sleep(Duration::from_millis(settings.gap)) {
+                        played = false;
+                        break;
+                    }
+                    match output.play(&sound, volume, fade_in, &shutdown) {
+                        Ok(..) => println!("Audio played"),
+                        Err(e) => {
+                            println!("Audio Cant be played : {}", e);
+                            played = false;
+                            break;
+                        }
+                    }
+                }
+                if let Some(done) = done {
+                    let _ = done.send(played);
+                }
+            }
+        }
+    }
+}
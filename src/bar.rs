@@ -0,0 +1,173 @@
+use crate::battery;
+use crate::clock::SystemClock;
+use crate::config::Config;
+use crate::estimate::format_duration;
+use crate::monitor::*;
+use crate::shutdown::Shutdown;
+use serde_json::json;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+// what the bar shows, printed again only when it changes
+#[derive(PartialEq)]
+struct BarState {
+    capacity: u64,
+    status: String,
+    level: Level,
+    // "1h 05m" to empty or to full
+    time: Option<String>,
+    tooltip: String,
+}
+
+impl BarState {
+    fn from_reading(reading: &Reading, level: Level) -> BarState {
+        let mut tooltip = format!("{}% {}", reading.capacity(), reading.status());
+        if let Some(e) = &reading.estimate {
+            tooltip.push_str(&format!("\n{}", e.describe()));
+        }
+        if level != Level::Normal {
+            tooltip.push_str(&format!("\nlevel: {}", level.as_str()));
+        }
+        BarState {
+            capacity: reading.capacity(),
+            status: reading.status().to_string(),
+            level,
+            time: reading.estimate.map(|e| format_duration(e.seconds)),
+            tooltip,
+        }
+    }
+
+    fn text(&self) -> String {
+        match &self.time {
+            Some(t) => format!("{}% ({})", self.capacity, t),
+            None => format!("{}%", self.capacity),
+        }
+    }
+
+    fn status_class(&self) -> String {
+        self.status.to_lowercase().replace(' ', "-")
+    }
+
+    // one line per update, see waybar-custom(5) with "return-type": "json"
+    fn waybar(&self) -> String {
+        json!({
+            "text": self.text(),
+            "alt": self.status_class(),
+            "tooltip": self.tooltip,
+            "class": [self.level.as_str(), self.status_class()],
+            "percentage": self.capacity,
+        })
+        .to_string()
+    }
+
+    // one block of the i3bar protocol, swaybar and i3status-rs read it too
+    fn i3bar(&self) -> String {
+        let mut block = json!({
+            "name": "batt_reminder",
+            "full_text": format!("{} {}", self.text(), self.status),
+            "short_text": format!("{}%", self.capacity),
+        });
+        let color = match self.level {
+            Level::Critical | Level::NearDead => Some("#FF0000"),
+            Level::Low => Some("#FFAE00"),
+            Level::Normal => None,
+        };
+        if let Some(c) = color {
+            block["color"] = json!(c);
+            block["urgent"] = json!(self.level != Level::Low);
+        }
+        format!("[{}],", block)
+    }
+
+    // i3blocks and polybar take the first line as the text
+    fn plain(&self) -> String {
+        format!("{} {}", self.text(), self.status)
+    }
+}
+
+// one line to the bar, false once it stopped reading
+fn emit(line: &str) -> bool {
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", line).and_then(|_| out.flush()).is_ok()
+}
+
+// `bar [--format waybar|i3bar|plain] [--interval <secs>]`, runs until killed
+// or until the bar closes our stdout
+pub fn run_subcommand(configuration: &Config, args: &[String]) -> i32 {
+    let mut format = "waybar".to_string();
+    let mut interval: u64 = 5;
+    let mut i = 0;
+    while i < args.len() {
+        let value = match args.get(i + 1) {
+            Some(v) => v,
+            None => {
+                println!("bar: missing value for {}", args[i]);
+                return 1;
+            }
+        };
+        match &args[i][..] {
+            "--format" => format = value.clone(),
+            "--interval" => match value.parse::<u64>() {
+                Ok(v) if v > 0 => interval = v,
+                _ => {
+                    println!("bar: invalid interval '{}'", value);
+                    return 1;
+                }
+            },
+            _ => {
+                println!("bar: unknown option {}", args[i]);
+                return 1;
+            }
+        }
+        i += 2;
+    }
+    let render: fn(&BarState) -> String = match &format[..] {
+        "waybar" => BarState::waybar,
+        "i3bar" => {
+            if !emit("{\"version\":1}\n[") {
+                return 0;
+            }
+            BarState::i3bar
+        }
+        "plain" => BarState::plain,
+        _ => {
+            println!(
+                "bar: unknown format '{}', use waybar, i3bar or plain",
+                format
+            );
+            return 1;
+        }
+    };
+
+    // the bar only needs the levels, the daemon does the rest
+    let mut configuration = configuration.clone();
+    configuration.health_check_enable = false;
+    configuration.drain_detect_enable = false;
+    let mut level = LevelMonitor::new(
+        &configuration,
+        Arc::new(SystemClock),
+        battery::source(&configuration),
+    );
+    let shutdown = Shutdown::new();
+    let mut last: Option<BarState> = None;
+    loop {
+        let (events, _) = level.poll();
+        for event in events {
+            match event {
+                Event::Polled { reading, level, .. } => {
+                    let state = BarState::from_reading(&reading, level);
+                    if last.as_ref() != Some(&state) {
+                        if !emit(&render(&state)) {
+                            return 0;
+                        }
+                        last = Some(state);
+                    }
+                }
+                Event::ReadError(e) => eprintln!("bar: {}", e),
+                _ => {}
+            }
+        }
+        shutdown.sleep(Duration::from_secs(interval));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::Config;
+use crate::estimate::Estimate;
+use crate::upower::UPowerBattery;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+// directory of the battery, e.g. /sys/class/power_supply/BAT1
+pub fn battery_dir(path_to_capacity: &str) -> PathBuf {
+    match Path::new(path_to_capacity).parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("/sys/class/power_supply/BAT1"),
+    }
+}
+
+// read a numeric sysfs attribute, None if the driver does not expose it
+pub fn read_u64_attr(dir: &Path, name: &str) -> Option<u64> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .and_then(|val| val.trim().parse::<u64>().ok())
+}
+
+pub fn read_string_attr(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|val| val.trim().to_string())
+        .filter(|val| !val.is_empty())
+}
+
+// where the monitor gets the capacity and status from
+pub trait BatterySource: Send + Sync {
+    fn capacity(&self) -> Result<u64, String>;
+    fn status(&self) -> Result<String, String>;
+    // directory with the energy/power attributes used for the estimates,
+    // None when the source has none
+    fn dir(&self) -> Option<PathBuf>;
+    // the source's own time estimate, None to have the monitor work one out
+    fn estimate(&self, _status: &str) -> Option<Estimate> {
+        None
+    }
+    // woken whenever the source saw the battery change, None for sources
+    // that can only be polled
+    fn changes(&self) -> Option<Receiver<()>> {
+        None
+    }
+}
+
+// the source the backend setting asks for. UPower falls back on sysfs when
+// the system bus cannot be reached
+pub fn source(configuration: &Config) -> Arc<dyn BatterySource> {
+    let sysfs = || {
+        Arc::new(SysfsBattery::new(
+            &configuration.path_to_capacity,
+            &configuration.path_to_status,
+        ))
+    };
+    match &configuration.backend[..] {
+        "upower" => match UPowerBattery::system(configuration) {
+            Ok(b) => Arc::new(b),
+            Err(e) => {
+                println!("upower: {}, reading sysfs", e);
+                sysfs()
+            }
+        },
+        "sysfs" => sysfs(),
+        other => {
+            println!("battery: unknown backend {}, reading sysfs", other);
+            sysfs()
+        }
+    }
+}
+
+// the capacity and status files of a power_supply in sysfs
+pub struct SysfsBattery {
+    path_to_capacity: String,
+    path_to_status: String,
+}
+
+impl SysfsBattery {
+    pub fn new(path_to_capacity: &str, path_to_status: &str) -> SysfsBattery {
+        SysfsBattery {
+            path_to_capacity: path_to_capacity.to_string(),
+            path_to_status: path_to_status.to_string(),
+        }
+    }
+}
+
+impl BatterySource for SysfsBattery {
+    fn capacity(&self) -> Result<u64, String> {
+        let contents = fs::read_to_string(&self.path_to_capacity)
+            .map_err(|e| format!("{} : {}", self.path_to_capacity, e))?;
+        contents
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("{} : {}", self.path_to_capacity, e))
+    }
+
+    fn status(&self) -> Result<String, String> {
+        fs::read_to_string(&self.path_to_status)
+            .map(|s| s.trim().to_string())
+            .map_err(|e| format!("{} : {}", self.path_to_status, e))
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        Some(battery_dir(&self.path_to_capacity))
+    }
+}
+
+// a battery held in memory, clones share it so one can be handed to the
+// monitor while the other changes it
+#[derive(Clone)]
+pub struct FakeBattery {
+    state: Arc<Mutex<(u64, String)>>,
+}
+
+impl FakeBattery {
+    pub fn new(capacity: u64, status: &str) -> FakeBattery {
+        FakeBattery {
+            state: Arc::new(Mutex::new((capacity, status.to_string()))),
+        }
+    }
+
+    pub fn set(&self, capacity: u64, status: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = (capacity, status.to_string());
+    }
+}
+
+impl BatterySource for FakeBattery {
+    fn capacity(&self) -> Result<u64, String> {
+        Ok(self.state.lock().unwrap_or_else(|e| e.into_inner()).0)
+    }
+
+    fn status(&self) -> Result<String, String> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .1
+            .clone())
+    }
+
+    fn dir(&self) -> Option<PathBuf> {
+        None
+    }
+}
@@ -0,0 +1,88 @@
+use crate::audio::Sound;
+use rodio::source::{SineWave, Source, Zero};
+use rodio::Decoder;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+const CHIME: &[u8] = include_bytes!("../assets/notification_sound.mp3");
+
+// SineWave always runs at 48kHz mono
+const TONE_RATE: u32 = 48000;
+const TONE_GAP_MS: u64 = 100;
+
+// "builtin:<name>" where <name> is an embedded file or a tone pattern made
+// of dash separated tones, e.g. "beep-beep-long"
+pub fn load(name: &str) -> Result<Sound, String> {
+    match name {
+        "chime" => decode_embedded(CHIME),
+        _ => tone_pattern(name),
+    }
+}
+
+pub fn names() -> Vec<&'static str> {
+    vec!["chime", "beep", "beep-beep", "beep-beep-long", "alarm"]
+}
+
+fn decode_embedded(bytes: &'static [u8]) -> Result<Sound, String> {
+    let decoder = Decoder::new(Cursor::new(bytes)).map_err(|e| e.to_string())?;
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<i16> = decoder.collect();
+    Ok(Sound {
+        channels,
+        sample_rate,
+        samples: Arc::new(samples),
+    })
+}
+
+// (frequency in Hz, length in ms) of each tone usable in a pattern
+fn tone(token: &str) -> Option<(f32, u64)> {
+    match token {
+        "beep" => Some((880.0, 150)),
+        "long" => Some((880.0, 600)),
+        "high" => Some((1320.0, 150)),
+        "low" => Some((440.0, 300)),
+        _ => None,
+    }
+}
+
+fn tone_pattern(name: &str) -> Result<Sound, String> {
+    let pattern = match name {
+        "alarm" => "high-low-high-low",
+        _ => name,
+    };
+    let mut samples: Vec<i16> = Vec::new();
+    for (i, token) in pattern.split('-').enumerate() {
+        let (freq, length) = match tone(token) {
+            Some(t) => t,
+            None => {
+                return Err(format!(
+                    "unknown builtin sound '{}', available: {}",
+                    name,
+                    names().join(", ")
+                ))
+            }
+        };
+        if i > 0 {
+            let gap =
+                Zero::<f32>::new(1, TONE_RATE).take_duration(Duration::from_millis(TONE_GAP_MS));
+            samples.extend(gap.map(to_i16));
+        }
+        // the short fades keep the tone from clicking
+        let sine = SineWave::new(freq)
+            .take_duration(Duration::from_millis(length))
+            .fade_in(Duration::from_millis(5))
+            .amplify(0.5);
+        samples.extend(sine.map(to_i16));
+    }
+    Ok(Sound {
+        channels: 1,
+        sample_rate: TONE_RATE,
+        samples: Arc::new(samples),
+    })
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
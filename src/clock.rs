@@ -0,0 +1,55 @@
+use crate::history::unix_now;
+use crate::shutdown::Shutdown;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// unix time in seconds, the monitor asks this instead of the system so a
+// simulation can run hours of battery life in an instant
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u64;
+    // waits `duration`, false once `shutdown` was triggered
+    fn sleep(&self, duration: Duration, shutdown: &Shutdown) -> bool;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        unix_now()
+    }
+
+    fn sleep(&self, duration: Duration, shutdown: &Shutdown) -> bool {
+        shutdown.sleep(duration)
+    }
+}
+
+// only moves when set or slept on, clones share the same time. Sleeping
+// returns at once and moves the time forward, so drive one loop with it
+#[derive(Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new(start: u64) -> VirtualClock {
+        VirtualClock {
+            now: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+
+    fn sleep(&self, duration: Duration, shutdown: &Shutdown) -> bool {
+        self.now.fetch_add(duration.as_secs(), Ordering::Relaxed);
+        !shutdown.is_triggered()
+    }
+}
@@ -1,5 +1,5 @@
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Deserialize, Serialize)]
 pub struct Data {
@@ -18,11 +18,87 @@ pub enum ConfigType {
     Integer(u64),
     Boolean(bool),
     StringArray(Vec<String>),
+    IntegerArray(Vec<u64>),
+    Table(HashMap<String, ConfigType>),
 }
 
-#[derive(Deserialize, Serialize)]
+// events that can have their own sound under [config.sounds.<event>]
+pub const SOUND_EVENTS: [&str; 6] = ["startup", "low", "critical", "alarm", "plug_in", "plug_out"];
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SoundConfig {
+    // sound file, "builtin:<name>", "none" for silence or "default" to use
+    // audio_path
+    pub path: String,
+    // percent, 100 plays the file as is
+    pub volume: u64,
+    pub repeat: u64,
+    // milliseconds between repeats
+    pub gap: u64,
+    // milliseconds, 0 disables the fade in
+    pub fade_in: u64,
+}
+
+impl SoundConfig {
+    pub fn default_for(event: &str) -> SoundConfig {
+        SoundConfig {
+            // the low level used to be silent, keep it that way by default
+            path: match event {
+                "low" => "none".to_string(),
+                "alarm" => "builtin:alarm".to_string(),
+                _ => "default".to_string(),
+            },
+            volume: 100,
+            repeat: 1,
+            gap: 500,
+            fade_in: 0,
+        }
+    }
+
+    fn apply(&mut self, table: HashMap<String, ConfigType>) {
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("path", ConfigType::String(v)) => self.path = v,
+                ("volume", ConfigType::Integer(v)) => self.volume = v,
+                ("repeat", ConfigType::Integer(v)) => self.repeat = v,
+                ("gap", ConfigType::Integer(v)) => self.gap = v,
+                ("fade_in", ConfigType::Integer(v)) => self.fade_in = v,
+                _ => {}
+            }
+        }
+    }
+}
+
+// per device settings under [config.peripherals.<name or model>], unset
+// thresholds fall back on peripheral_low / peripheral_critical
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PeripheralConfig {
+    pub low: Option<u64>,
+    pub critical: Option<u64>,
+    // "{name}", "{level}" and "{capacity}" are filled in, empty for the
+    // default message
+    pub message: String,
+    pub ignore: bool,
+}
+
+impl PeripheralConfig {
+    fn apply(&mut self, table: HashMap<String, ConfigType>) {
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("low", ConfigType::Integer(v)) => self.low = Some(v),
+                ("critical", ConfigType::Integer(v)) => self.critical = Some(v),
+                ("message", ConfigType::String(v)) => self.message = v,
+                ("ignore", ConfigType::Boolean(v)) => self.ignore = v,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub audio_path: String,
+    pub audio_backend: String,
     pub battery_critical: u64,
     pub battery_low: u64,
     pub normal_sleep_time: u64,
@@ -30,9 +106,9 @@ pub struct Config {
     pub critical_sleep_time: u64,
     pub starting_bleep: bool,
     pub target_session: Vec<String>,
+    pub exclude_session: Vec<String>,
     pub enable_plug_in_check: bool,
     pub plug_in_check_interval: u64,
-    pub signal_check_interval: u64,
     pub path_to_capacity: String,
     pub path_to_status: String,
     pub near_ded_command: String,
@@ -40,12 +116,68 @@ pub struct Config {
     pub bat_crit_command_to_exec: String,
     pub plug_in_check_command_to_exec: String,
     pub plug_out_check_command_to_exec: String,
+    pub history_enable: bool,
+    pub history_retention_days: u64,
+    pub history_compact_after_days: u64,
+    pub history_compact_resolution: u64,
+    pub estimate_window: u64,
+    pub time_low_minutes: u64,
+    pub time_critical_minutes: u64,
+    pub time_threshold_confirm: u64,
+    pub health_check_enable: bool,
+    pub health_alert_levels: Vec<u64>,
+    pub drain_detect_enable: bool,
+    pub drain_spike_percent: u64,
+    pub drain_cooldown: u64,
+    pub drain_action: String,
+    pub alarm_enable: bool,
+    pub alarm_max_duration: u64,
+    pub alarm_volume_step: u64,
+    pub alarm_max_volume: u64,
+    pub quiet_hours: Vec<String>,
+    pub quiet_mute_sounds: bool,
+    pub quiet_lower_urgency: bool,
+    pub quiet_allow_levels: Vec<String>,
+    pub quiet_follow_dnd: bool,
+    pub shutdown_hook_policy: String,
+    pub shutdown_hook_timeout: u64,
+    pub state_file_enable: bool,
+    pub metrics_mode: String,
+    pub metrics_listen: String,
+    pub metrics_textfile: String,
+    pub webhook_urls: Vec<String>,
+    pub webhook_headers: Vec<String>,
+    pub webhook_secret: String,
+    pub webhook_retries: u64,
+    pub webhook_backoff: u64,
+    pub webhook_queue_size: u64,
+    pub webhook_timeout: u64,
+    pub mqtt_broker: String,
+    pub mqtt_client_id: String,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_qos: u64,
+    pub mqtt_retain: bool,
+    pub mqtt_discovery_prefix: String,
+    pub mqtt_keepalive: u64,
+    pub backend: String,
+    pub upower_device: String,
+    pub peripheral_check_enable: bool,
+    pub peripheral_check_interval: u64,
+    pub peripheral_low: u64,
+    pub peripheral_critical: u64,
+    pub peripheral_alert_interval: u64,
+    pub peripherals: BTreeMap<String, PeripheralConfig>,
+    pub sounds: BTreeMap<String, SoundConfig>,
 }
 
 impl Config {
+    #[allow(clippy::needless_return)]
     pub fn default_config() -> Config {
         return Config {
-            audio_path: "none".to_string(),
+            audio_path: "builtin:chime".to_string(),
+            audio_backend: "rodio".to_string(),
             battery_critical: 30,
             battery_low: 45,
             normal_sleep_time: 300,
@@ -53,9 +185,9 @@ impl Config {
             critical_sleep_time: 120,
             starting_bleep: false,
             target_session: vec!["any".to_string()],
+            exclude_session: vec![],
             enable_plug_in_check: true,
             plug_in_check_interval: 2,
-            signal_check_interval: 1000,
             path_to_status: "/sys/class/power_supply/BAT1/status".to_string(),
             path_to_capacity: "/sys/class/power_supply/BAT1/capacity".to_string(),
             near_ded_command: "systemctl poweroff".to_string(),
@@ -63,12 +195,72 @@ impl Config {
             bat_crit_command_to_exec: "".to_string(),
             plug_in_check_command_to_exec: "".to_string(),
             plug_out_check_command_to_exec: "".to_string(),
+            history_enable: true,
+            history_retention_days: 30,
+            history_compact_after_days: 3,
+            history_compact_resolution: 600,
+            estimate_window: 1800,
+            time_low_minutes: 0,
+            time_critical_minutes: 0,
+            time_threshold_confirm: 3,
+            health_check_enable: true,
+            health_alert_levels: vec![80, 70, 60],
+            drain_detect_enable: true,
+            drain_spike_percent: 200,
+            drain_cooldown: 1800,
+            drain_action: "none".to_string(),
+            alarm_enable: false,
+            alarm_max_duration: 300,
+            alarm_volume_step: 10,
+            alarm_max_volume: 150,
+            quiet_hours: vec![],
+            quiet_mute_sounds: true,
+            quiet_lower_urgency: true,
+            quiet_allow_levels: vec!["critical".to_string(), "near_dead".to_string()],
+            quiet_follow_dnd: true,
+            shutdown_hook_policy: "wait".to_string(),
+            shutdown_hook_timeout: 10,
+            state_file_enable: true,
+            metrics_mode: "off".to_string(),
+            metrics_listen: "127.0.0.1:9812".to_string(),
+            metrics_textfile: "/var/lib/node_exporter/textfile_collector/batt_reminder.prom"
+                .to_string(),
+            webhook_urls: vec![],
+            webhook_headers: vec![],
+            webhook_secret: "".to_string(),
+            webhook_retries: 20,
+            webhook_backoff: 5,
+            webhook_queue_size: 100,
+            webhook_timeout: 10,
+            mqtt_broker: "".to_string(),
+            mqtt_client_id: "".to_string(),
+            mqtt_username: "".to_string(),
+            mqtt_password: "".to_string(),
+            mqtt_topic_prefix: "batt_reminder/{host}".to_string(),
+            mqtt_qos: 0,
+            mqtt_retain: true,
+            mqtt_discovery_prefix: "homeassistant".to_string(),
+            mqtt_keepalive: 60,
+            backend: "sysfs".to_string(),
+            upower_device: "".to_string(),
+            peripheral_check_enable: true,
+            peripheral_check_interval: 60,
+            peripheral_low: 20,
+            peripheral_critical: 10,
+            peripheral_alert_interval: 3600,
+            peripherals: BTreeMap::new(),
+            sounds: SOUND_EVENTS
+                .iter()
+                .map(|e| (e.to_string(), SoundConfig::default_for(e)))
+                .collect(),
         };
     }
 
+    #[allow(clippy::unused_unit)]
     pub fn print_debug(&self) -> () {
         println!(" == Configuration == ");
         println!("\taudio_path : {}", self.audio_path);
+        println!("\taudio_backend : {}", self.audio_backend);
         println!("\tbattery_critical : {}", self.battery_critical);
         println!("\tbattery_low : {}", self.battery_low);
         println!("\tnormal_sleep_time : {}", self.normal_sleep_time);
@@ -76,9 +268,9 @@ impl Config {
         println!("\tcritical_sleep_time : {}", self.critical_sleep_time);
         println!("\tstarting_bleep : {}", self.starting_bleep);
         println!("\ttarget_session : {:?}", self.target_session);
+        println!("\texclude_session : {:?}", self.exclude_session);
         println!("\tenable_plug_in_check : {}", self.enable_plug_in_check);
         println!("\tplug_in_check_interval : {}", self.plug_in_check_interval);
-        println!("\tsignal_check_interval : {}", self.signal_check_interval);
         println!("\tpath_to_status : {}", self.path_to_status);
         println!("\tpath_to_capacity : {}", self.path_to_capacity);
         println!("\tnear_ded_command : {}", self.near_ded_command);
@@ -98,9 +290,101 @@ impl Config {
             "\tplug_out_check_command_to_exec : {}",
             self.plug_out_check_command_to_exec
         );
+        println!("\thistory_enable : {}", self.history_enable);
+        println!("\thistory_retention_days : {}", self.history_retention_days);
+        println!(
+            "\thistory_compact_after_days : {}",
+            self.history_compact_after_days
+        );
+        println!(
+            "\thistory_compact_resolution : {}",
+            self.history_compact_resolution
+        );
+        println!("\testimate_window : {}", self.estimate_window);
+        println!("\ttime_low_minutes : {}", self.time_low_minutes);
+        println!("\ttime_critical_minutes : {}", self.time_critical_minutes);
+        println!("\ttime_threshold_confirm : {}", self.time_threshold_confirm);
+        println!("\thealth_check_enable : {}", self.health_check_enable);
+        println!("\thealth_alert_levels : {:?}", self.health_alert_levels);
+        println!("\tdrain_detect_enable : {}", self.drain_detect_enable);
+        println!("\tdrain_spike_percent : {}", self.drain_spike_percent);
+        println!("\tdrain_cooldown : {}", self.drain_cooldown);
+        println!("\tdrain_action : {}", self.drain_action);
+        println!("\talarm_enable : {}", self.alarm_enable);
+        println!("\talarm_max_duration : {}", self.alarm_max_duration);
+        println!("\talarm_volume_step : {}", self.alarm_volume_step);
+        println!("\talarm_max_volume : {}", self.alarm_max_volume);
+        println!("\tquiet_hours : {:?}", self.quiet_hours);
+        println!("\tquiet_mute_sounds : {}", self.quiet_mute_sounds);
+        println!("\tquiet_lower_urgency : {}", self.quiet_lower_urgency);
+        println!("\tquiet_allow_levels : {:?}", self.quiet_allow_levels);
+        println!("\tquiet_follow_dnd : {}", self.quiet_follow_dnd);
+        println!("\tshutdown_hook_policy : {}", self.shutdown_hook_policy);
+        println!("\tshutdown_hook_timeout : {}", self.shutdown_hook_timeout);
+        println!("\tstate_file_enable : {}", self.state_file_enable);
+        println!("\tmetrics_mode : {}", self.metrics_mode);
+        println!("\tmetrics_listen : {}", self.metrics_listen);
+        println!("\tmetrics_textfile : {}", self.metrics_textfile);
+        println!("\twebhook_urls : {:?}", self.webhook_urls);
+        // header values and the secret are often credentials
+        let header_names: Vec<&str> = self
+            .webhook_headers
+            .iter()
+            .map(|h| h.split(':').next().unwrap_or("").trim())
+            .collect();
+        println!("\twebhook_headers : {:?}", header_names);
+        println!(
+            "\twebhook_secret : {}",
+            match self.webhook_secret.is_empty() {
+                true => "(none)",
+                false => "(set)",
+            }
+        );
+        println!("\twebhook_retries : {}", self.webhook_retries);
+        println!("\twebhook_backoff : {}", self.webhook_backoff);
+        println!("\twebhook_queue_size : {}", self.webhook_queue_size);
+        println!("\twebhook_timeout : {}", self.webhook_timeout);
+        println!("\tmqtt_broker : {}", self.mqtt_broker);
+        println!("\tmqtt_client_id : {}", self.mqtt_client_id);
+        println!("\tmqtt_username : {}", self.mqtt_username);
+        println!(
+            "\tmqtt_password : {}",
+            match self.mqtt_password.is_empty() {
+                true => "(none)",
+                false => "(set)",
+            }
+        );
+        println!("\tmqtt_topic_prefix : {}", self.mqtt_topic_prefix);
+        println!("\tmqtt_qos : {}", self.mqtt_qos);
+        println!("\tmqtt_retain : {}", self.mqtt_retain);
+        println!("\tmqtt_discovery_prefix : {}", self.mqtt_discovery_prefix);
+        println!("\tmqtt_keepalive : {}", self.mqtt_keepalive);
+        println!("\tbackend : {}", self.backend);
+        println!("\tupower_device : {}", self.upower_device);
+        println!(
+            "\tperipheral_check_enable : {}",
+            self.peripheral_check_enable
+        );
+        println!(
+            "\tperipheral_check_interval : {}",
+            self.peripheral_check_interval
+        );
+        println!("\tperipheral_low : {}", self.peripheral_low);
+        println!("\tperipheral_critical : {}", self.peripheral_critical);
+        println!(
+            "\tperipheral_alert_interval : {}",
+            self.peripheral_alert_interval
+        );
+        for (device, peripheral) in &self.peripherals {
+            println!("\tperipherals.{} : {:?}", device, peripheral);
+        }
+        for (event, sound) in &self.sounds {
+            println!("\tsounds.{} : {:?}", event, sound);
+        }
         println!(" == ~/.config/batt_reminder.toml == ");
     }
 
+    #[allow(clippy::unnecessary_cast)]
     pub fn convert_data(&self, data: Data) -> Config {
         let mut config = Config::default_config();
         for (key, value) in data.config {
@@ -110,6 +394,11 @@ impl Config {
                         config.audio_path = v;
                     }
                 }
+                "audio_backend" => {
+                    if let ConfigType::String(v) = value {
+                        config.audio_backend = v;
+                    }
+                }
                 "battery_critical" => {
                     if let ConfigType::Integer(v) = value {
                         config.battery_critical = v;
@@ -145,6 +434,11 @@ impl Config {
                         config.target_session = v;
                     }
                 }
+                "exclude_session" => {
+                    if let ConfigType::StringArray(v) = value {
+                        config.exclude_session = v;
+                    }
+                }
                 "enable_plug_in_check" => {
                     if let ConfigType::Boolean(v) = value {
                         config.enable_plug_in_check = v;
@@ -155,11 +449,6 @@ impl Config {
                         config.plug_in_check_interval = v as u64;
                     }
                 }
-                "signal_check_interval" => {
-                    if let ConfigType::Integer(v) = value {
-                        config.signal_check_interval = v as u64;
-                    }
-                }
                 "path_to_capacity" => {
                     if let ConfigType::String(v) = value {
                         config.path_to_capacity = v;
@@ -195,10 +484,307 @@ impl Config {
                         config.plug_out_check_command_to_exec = v;
                     }
                 }
+                "history_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.history_enable = v;
+                    }
+                }
+                "history_retention_days" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.history_retention_days = v;
+                    }
+                }
+                "history_compact_after_days" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.history_compact_after_days = v;
+                    }
+                }
+                "history_compact_resolution" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.history_compact_resolution = v;
+                    }
+                }
+                "estimate_window" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.estimate_window = v;
+                    }
+                }
+                "time_low_minutes" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.time_low_minutes = v;
+                    }
+                }
+                "time_critical_minutes" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.time_critical_minutes = v;
+                    }
+                }
+                "time_threshold_confirm" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.time_threshold_confirm = v;
+                    }
+                }
+                "health_check_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.health_check_enable = v;
+                    }
+                }
+                "health_alert_levels" => match value {
+                    ConfigType::IntegerArray(v) => config.health_alert_levels = v,
+                    // `[]` reads as an empty StringArray, it turns the alerts off
+                    ConfigType::StringArray(v) if v.is_empty() => {
+                        config.health_alert_levels = vec![];
+                    }
+                    _ => {}
+                },
+                "drain_detect_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.drain_detect_enable = v;
+                    }
+                }
+                "drain_spike_percent" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.drain_spike_percent = v;
+                    }
+                }
+                "drain_cooldown" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.drain_cooldown = v;
+                    }
+                }
+                "drain_action" => {
+                    if let ConfigType::String(v) = value {
+                        config.drain_action = v;
+                    }
+                }
+                "alarm_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.alarm_enable = v;
+                    }
+                }
+                "alarm_max_duration" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.alarm_max_duration = v;
+                    }
+                }
+                "alarm_volume_step" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.alarm_volume_step = v;
+                    }
+                }
+                "alarm_max_volume" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.alarm_max_volume = v;
+                    }
+                }
+                "quiet_hours" => {
+                    if let ConfigType::StringArray(v) = value {
+                        config.quiet_hours = v;
+                    }
+                }
+                "quiet_mute_sounds" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.quiet_mute_sounds = v;
+                    }
+                }
+                "quiet_lower_urgency" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.quiet_lower_urgency = v;
+                    }
+                }
+                "quiet_allow_levels" => {
+                    if let ConfigType::StringArray(v) = value {
+                        config.quiet_allow_levels = v;
+                    }
+                }
+                "quiet_follow_dnd" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.quiet_follow_dnd = v;
+                    }
+                }
+                "shutdown_hook_policy" => {
+                    if let ConfigType::String(v) = value {
+                        config.shutdown_hook_policy = v;
+                    }
+                }
+                "shutdown_hook_timeout" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.shutdown_hook_timeout = v;
+                    }
+                }
+                "state_file_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.state_file_enable = v;
+                    }
+                }
+                "metrics_mode" => {
+                    if let ConfigType::String(v) = value {
+                        config.metrics_mode = v;
+                    }
+                }
+                "metrics_listen" => {
+                    if let ConfigType::String(v) = value {
+                        config.metrics_listen = v;
+                    }
+                }
+                "metrics_textfile" => {
+                    if let ConfigType::String(v) = value {
+                        config.metrics_textfile = v;
+                    }
+                }
+                "webhook_urls" => {
+                    if let ConfigType::StringArray(v) = value {
+                        config.webhook_urls = v;
+                    }
+                }
+                "webhook_headers" => {
+                    if let ConfigType::StringArray(v) = value {
+                        config.webhook_headers = v;
+                    }
+                }
+                "webhook_secret" => {
+                    if let ConfigType::String(v) = value {
+                        config.webhook_secret = v;
+                    }
+                }
+                "webhook_retries" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.webhook_retries = v;
+                    }
+                }
+                "webhook_backoff" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.webhook_backoff = v;
+                    }
+                }
+                "webhook_queue_size" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.webhook_queue_size = v;
+                    }
+                }
+                "webhook_timeout" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.webhook_timeout = v;
+                    }
+                }
+                "mqtt_broker" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_broker = v;
+                    }
+                }
+                "mqtt_client_id" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_client_id = v;
+                    }
+                }
+                "mqtt_username" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_username = v;
+                    }
+                }
+                "mqtt_password" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_password = v;
+                    }
+                }
+                "mqtt_topic_prefix" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_topic_prefix = v;
+                    }
+                }
+                "mqtt_qos" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.mqtt_qos = v;
+                    }
+                }
+                "mqtt_retain" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.mqtt_retain = v;
+                    }
+                }
+                "mqtt_discovery_prefix" => {
+                    if let ConfigType::String(v) = value {
+                        config.mqtt_discovery_prefix = v;
+                    }
+                }
+                "mqtt_keepalive" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.mqtt_keepalive = v;
+                    }
+                }
+                "backend" => {
+                    if let ConfigType::String(v) = value {
+                        config.backend = v;
+                    }
+                }
+                "upower_device" => {
+                    if let ConfigType::String(v) = value {
+                        config.upower_device = v;
+                    }
+                }
+                "peripheral_check_enable" => {
+                    if let ConfigType::Boolean(v) = value {
+                        config.peripheral_check_enable = v;
+                    }
+                }
+                "peripheral_check_interval" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.peripheral_check_interval = v;
+                    }
+                }
+                "peripheral_low" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.peripheral_low = v;
+                    }
+                }
+                "peripheral_critical" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.peripheral_critical = v;
+                    }
+                }
+                "peripheral_alert_interval" => {
+                    if let ConfigType::Integer(v) = value {
+                        config.peripheral_alert_interval = v;
+                    }
+                }
+                "peripherals" => {
+                    if let ConfigType::Table(devices) = value {
+                        for (device, settings) in devices {
+                            if let ConfigType::Table(fields) = settings {
+                                config.peripherals.entry(device).or_default().apply(fields);
+                            }
+                        }
+                    }
+                }
+                "sounds" => {
+                    if let ConfigType::Table(events) = value {
+                        for (event, sound) in events {
+                            if let ConfigType::Table(fields) = sound {
+                                config
+                                    .sounds
+                                    .entry(event.clone())
+                                    .or_insert_with(|| SoundConfig::default_for(&event))
+                                    .apply(fields);
+                            }
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
         config
     }
+
+    // sound settings of an event with "default" resolved to audio_path
+    pub fn sound(&self, event: &str) -> SoundConfig {
+        let mut sound = match self.sounds.get(event) {
+            Some(s) => s.clone(),
+            None => SoundConfig::default_for(event),
+        };
+        if sound.path == "default" {
+            sound.path = self.audio_path.clone();
+        }
+        sound
+    }
 }
@@ -0,0 +1,122 @@
+use crate::alarm::Alarm;
+use crate::history::unix_now;
+use crate::paths;
+use crate::snooze::Snooze;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+// seconds a client gets to send its command, so a silent one cannot hold up
+// the next
+const CLIENT_TIMEOUT: u64 = 2;
+// commands are a few words, a longer line is cut here
+const MAX_LINE: u64 = 1024;
+
+pub fn socket_path() -> PathBuf {
+    paths::runtime_dir().join("batt_reminder.sock")
+}
+
+// listens on the control socket, one command per line, the handler's answer
+// is written back to the client
+pub fn start_server<F>(handler: F)
+where
+    F: Fn(&str) -> String + Send + 'static,
+{
+    let path = socket_path();
+    // only one instance runs at a time, so an existing socket is stale
+    let _ = fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(l) => l,
+        Err(e) => {
+            println!("control: failed to bind {} : {}", path.display(), e);
+            return;
+        }
+    };
+    println!("control: listening on {}", path.display());
+    thread::spawn(move || serve(listener, handler));
+}
+
+// answers the clients of `listener` one after the other
+pub fn serve<F>(listener: UnixListener, handler: F)
+where
+    F: Fn(&str) -> String,
+{
+    for stream in listener.incoming().flatten() {
+        let timeout = Some(Duration::from_secs(CLIENT_TIMEOUT));
+        let _ = stream.set_read_timeout(timeout);
+        let _ = stream.set_write_timeout(timeout);
+        let mut line = String::new();
+        let mut reader = BufReader::new((&stream).take(MAX_LINE));
+        if reader.read_line(&mut line).is_err() {
+            continue;
+        }
+        let reply = handler(line.trim());
+        let _ = (&stream).write_all(format!("{}\n", reply).as_bytes());
+    }
+}
+
+// sends one command to the running daemon and returns its answer
+pub fn send_command(command: &str) -> Result<String, String> {
+    let path = socket_path();
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| format!("could not connect to {} : {}", path.display(), e))?;
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .map_err(|e| e.to_string())?;
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| e.to_string())?;
+    Ok(reply.trim().to_string())
+}
+
+// what the daemon does with a command from the control socket or the MQTT
+// command topic: "ack", "snooze <minutes>", "pause" or "resume"
+pub fn daemon_command(command: &str, alarm: &Alarm, snooze: &Snooze) -> String {
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words[..] {
+        ["ack"] => match alarm.acknowledge() {
+            true => "alarm acknowledged".to_string(),
+            false => "no alarm is running".to_string(),
+        },
+        ["snooze", minutes] => match minutes.parse::<u64>() {
+            Ok(m) if m > 0 => {
+                snooze.snooze(unix_now(), m);
+                // a running alarm would keep ringing through the snooze
+                alarm.acknowledge();
+                format!("reminders snoozed for {} minute(s)", m)
+            }
+            _ => format!("invalid number of minutes '{}'", minutes),
+        },
+        ["snooze"] => "usage: snooze <minutes>".to_string(),
+        ["pause"] => {
+            snooze.pause();
+            alarm.acknowledge();
+            "reminders paused until resumed".to_string()
+        }
+        ["resume"] => {
+            snooze.resume();
+            "reminders resumed".to_string()
+        }
+        _ => format!("unknown command '{}'", command),
+    }
+}
+
+// `ack`, `snooze <minutes>`, `pause`, `resume` forwarded to the daemon
+pub fn run_subcommand(command: &str) -> i32 {
+    match send_command(command) {
+        Ok(reply) => {
+            println!("{}", reply);
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            1
+        }
+    }
+}
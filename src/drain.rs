@@ -0,0 +1,168 @@
+use crate::battery::*;
+use crate::config::Config;
+use crate::estimate::Estimator;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// samples needed before the baseline is trusted
+const MIN_BASELINE_SAMPLES: u64 = 10;
+// weight of a new sample in the rolling baseline
+const BASELINE_ALPHA: f64 = 0.05;
+// processes named in the notification
+const TOP_PROCESSES: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    pub name: String,
+    // CPU time used between the two polls, in clock ticks
+    pub ticks: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DrainReport {
+    pub rate: f64,
+    pub baseline: f64,
+    pub top: Vec<ProcessUsage>,
+}
+
+// tracks the discharge rate (percent per hour) against a slow rolling
+// baseline and reports when it suddenly gets much higher than usual
+pub struct DrainDetector {
+    spike_percent: u64,
+    cooldown: u64,
+    baseline: Option<f64>,
+    samples: u64,
+    last_alert: u64,
+    // cpu ticks per pid at the previous poll
+    snapshot: HashMap<u32, (String, u64)>,
+}
+
+impl DrainDetector {
+    pub fn new(configuration: &Config) -> DrainDetector {
+        DrainDetector {
+            spike_percent: configuration.drain_spike_percent,
+            cooldown: configuration.drain_cooldown,
+            baseline: None,
+            samples: 0,
+            last_alert: 0,
+            snapshot: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.snapshot.clear();
+    }
+
+    // `dir` is the battery's sysfs directory, the estimator's slope is used
+    // when there is none
+    pub fn update(
+        &mut self,
+        dir: Option<&Path>,
+        estimator: &Estimator,
+        now: u64,
+    ) -> Option<DrainReport> {
+        let current = proc_snapshot();
+        let previous = std::mem::replace(&mut self.snapshot, current);
+        let rate = match discharge_rate(dir, estimator) {
+            Some(r) if r > 0.0 => r,
+            _ => return None,
+        };
+        let baseline = match self.baseline {
+            Some(b) => b,
+            None => {
+                self.baseline = Some(rate);
+                self.samples = 1;
+                return None;
+            }
+        };
+        let spiking = self.samples >= MIN_BASELINE_SAMPLES
+            && rate * 100.0 > baseline * self.spike_percent as f64;
+        // spikes are kept out of the baseline so a long drain stays abnormal
+        if !spiking {
+            self.baseline = Some(BASELINE_ALPHA * rate + (1.0 - BASELINE_ALPHA) * baseline);
+            self.samples += 1;
+            return None;
+        }
+        if now < self.last_alert + self.cooldown || previous.is_empty() {
+            return None;
+        }
+        self.last_alert = now;
+        Some(DrainReport {
+            rate,
+            baseline,
+            top: top_processes(&previous, &self.snapshot),
+        })
+    }
+}
+
+// percent per hour, from power_now when the driver has it or from the
+// regression over recent capacity samples otherwise
+fn discharge_rate(dir: Option<&Path>, estimator: &Estimator) -> Option<f64> {
+    let attr = |name| dir.and_then(|d| read_u64_attr(d, name));
+    let from_power = match (attr("power_now"), attr("energy_full")) {
+        (Some(power), Some(full)) if full > 0 => Some(power as f64 * 100.0 / full as f64),
+        _ => None,
+    };
+    let from_current = match (attr("current_now"), attr("charge_full")) {
+        (Some(current), Some(full)) if full > 0 => Some(current as f64 * 100.0 / full as f64),
+        _ => None,
+    };
+    from_power
+        .or(from_current)
+        .or_else(|| estimator.slope().map(|s| -s * 3600.0))
+}
+
+fn proc_snapshot() -> HashMap<u32, (String, u64)> {
+    let mut snapshot = HashMap::new();
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return snapshot,
+    };
+    for entry in entries.flatten() {
+        let pid: u32 = match entry.file_name().to_string_lossy().parse() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        if let Some(stat) = read_proc_stat(pid) {
+            snapshot.insert(pid, stat);
+        }
+    }
+    snapshot
+}
+
+// (comm, utime + stime) from /proc/<pid>/stat, the comm is in parentheses
+// and may itself contain spaces so the rest is split after the last ')'
+fn read_proc_stat(pid: u32) -> Option<(String, u64)> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat[open + 1..close].to_string();
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    // fields[0] is the state (field 3), utime and stime are fields 14 and 15
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((name, utime + stime))
+}
+
+fn top_processes(
+    previous: &HashMap<u32, (String, u64)>,
+    current: &HashMap<u32, (String, u64)>,
+) -> Vec<ProcessUsage> {
+    let mut usage: Vec<ProcessUsage> = current
+        .iter()
+        .filter_map(|(pid, (name, ticks))| {
+            let (_, before) = previous.get(pid)?;
+            Some(ProcessUsage {
+                pid: *pid,
+                name: name.clone(),
+                ticks: ticks.saturating_sub(*before),
+            })
+        })
+        .filter(|p| p.ticks > 0)
+        .collect();
+    usage.sort_by_key(|p| std::cmp::Reverse(p.ticks));
+    usage.truncate(TOP_PROCESSES);
+    usage
+}
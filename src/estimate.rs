@@ -0,0 +1,275 @@
+use crate::battery::*;
+use std::collections::VecDeque;
+use std::path::Path;
+
+// fewer samples than this and the regression is only a rough guess
+const MIN_REGRESSION_SAMPLES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Confidence {
+    High,
+    Medium,
+    Low,
+}
+
+impl Confidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Confidence::High => "high",
+            Confidence::Medium => "medium",
+            Confidence::Low => "low",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate {
+    pub seconds: u64,
+    pub to_full: bool,
+    pub confidence: Confidence,
+}
+
+impl Estimate {
+    // "1h 05m remaining", "~0h 40m until full (low confidence)"
+    pub fn describe(&self) -> String {
+        let what = match self.to_full {
+            true => "until full",
+            false => "remaining",
+        };
+        let time = format_duration(self.seconds);
+        match self.confidence {
+            Confidence::Low => format!("~{} {} (low confidence)", time, what),
+            _ => format!("{} {}", time, what),
+        }
+    }
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    format!("{}h {:02}m", seconds / 3600, (seconds % 3600) / 60)
+}
+
+// keeps the (timestamp, capacity) samples of the last `window` seconds to
+// fall back on a linear regression when the driver reports no power/current
+pub struct Estimator {
+    samples: VecDeque<(u64, u64)>,
+    window: u64,
+    // status of the samples kept
+    status: String,
+}
+
+impl Estimator {
+    pub fn new(window: u64) -> Estimator {
+        Estimator {
+            samples: VecDeque::new(),
+            window,
+            status: String::new(),
+        }
+    }
+
+    pub fn push(&mut self, timestamp: u64, capacity: u64, status: &str) {
+        // a plug event makes the previous trend meaningless, charging and
+        // discharging samples never mix
+        if status != self.status {
+            self.samples.clear();
+            self.status = status.to_string();
+        }
+        if status != "Discharging" && status != "Charging" {
+            return;
+        }
+        self.samples.push_back((timestamp, capacity));
+        while let Some(&(ts, _)) = self.samples.front() {
+            if timestamp.saturating_sub(ts) > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // `dir` is the battery directory with the energy/power attributes, without
+    // one only the regression is used
+    pub fn estimate(&self, dir: Option<&Path>, status: &str, capacity: u64) -> Option<Estimate> {
+        let to_full = match status {
+            "Discharging" => false,
+            "Charging" => true,
+            _ => return None,
+        };
+        match dir.and_then(|d| estimate_from_sysfs(d, to_full)) {
+            Some(seconds) => Some(Estimate {
+                seconds,
+                to_full,
+                confidence: Confidence::High,
+            }),
+            None => self.estimate_from_samples(capacity, to_full),
+        }
+    }
+
+    // percent per second, negative while discharging
+    pub fn slope(&self) -> Option<f64> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+        let n = self.samples.len() as f64;
+        let t0 = self.samples[0].0 as f64;
+        let mean_t = self.samples.iter().map(|s| s.0 as f64 - t0).sum::<f64>() / n;
+        let mean_c = self.samples.iter().map(|s| s.1 as f64).sum::<f64>() / n;
+        let mut num = 0.0;
+        let mut den = 0.0;
+        for &(ts, cap) in &self.samples {
+            let dt = ts as f64 - t0 - mean_t;
+            num += dt * (cap as f64 - mean_c);
+            den += dt * dt;
+        }
+        if den == 0.0 {
+            return None;
+        }
+        Some(num / den)
+    }
+
+    fn estimate_from_samples(&self, capacity: u64, to_full: bool) -> Option<Estimate> {
+        let slope = self.slope()?;
+        let seconds = match to_full {
+            true if slope > 0.0 => (100u64.saturating_sub(capacity)) as f64 / slope,
+            false if slope < 0.0 => capacity as f64 / -slope,
+            _ => return None,
+        };
+        let confidence = match self.samples.len() < MIN_REGRESSION_SAMPLES {
+            true => Confidence::Low,
+            false => Confidence::Medium,
+        };
+        Some(Estimate {
+            seconds: seconds as u64,
+            to_full,
+            confidence,
+        })
+    }
+}
+
+// energy_* are in µWh with power_now in µW, charge_* in µAh with current_now
+// in µA, either pair gives hours directly
+fn estimate_from_sysfs(dir: &Path, to_full: bool) -> Option<u64> {
+    let pairs = [
+        ("energy_now", "energy_full", "power_now"),
+        ("charge_now", "charge_full", "current_now"),
+    ];
+    for (now_attr, full_attr, rate_attr) in pairs {
+        let rate = match read_u64_attr(dir, rate_attr) {
+            Some(r) if r > 0 => r,
+            _ => continue,
+        };
+        let now = match read_u64_attr(dir, now_attr) {
+            Some(n) => n,
+            None => continue,
+        };
+        let amount = match to_full {
+            true => match read_u64_attr(dir, full_attr) {
+                Some(full) => full.saturating_sub(now),
+                None => continue,
+            },
+            false => now,
+        };
+        return Some(amount * 3600 / rate);
+    }
+    None
+}
+
+// environment passed to hook commands
+pub fn hook_env(capacity: u64, status: &str, estimate: &Option<Estimate>) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("BATT_CAPACITY".to_string(), capacity.to_string()),
+        ("BATT_STATUS".to_string(), status.to_string()),
+    ];
+    if let Some(e) = estimate {
+        env.push(("BATT_TIME_REMAINING".to_string(), e.seconds.to_string()));
+        env.push((
+            "BATT_TIME_REMAINING_KIND".to_string(),
+            match e.to_full {
+                true => "to_full".to_string(),
+                false => "to_empty".to_string(),
+            },
+        ));
+        env.push((
+            "BATT_TIME_REMAINING_CONFIDENCE".to_string(),
+            e.confidence.as_str().to_string(),
+        ));
+    }
+    env
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+// smooths the time-to-empty estimate and only reports a level once it has
+// been below the threshold for `confirm` polls in a row, so one noisy sample
+// (e.g. a short power spike) cannot trigger the critical hook
+pub struct TimeThresholds {
+    low_secs: u64,
+    critical_secs: u64,
+    confirm: u64,
+    alpha: f64,
+    smoothed: Option<f64>,
+    low_hits: u64,
+    critical_hits: u64,
+}
+
+impl TimeThresholds {
+    pub fn new(low_minutes: u64, critical_minutes: u64, confirm: u64) -> TimeThresholds {
+        TimeThresholds {
+            low_secs: low_minutes.saturating_mul(60),
+            critical_secs: critical_minutes.saturating_mul(60),
+            confirm: confirm.max(1),
+            alpha: 0.3,
+            smoothed: None,
+            low_hits: 0,
+            critical_hits: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.smoothed = None;
+        self.low_hits = 0;
+        self.critical_hits = 0;
+    }
+
+    pub fn smoothed_seconds(&self) -> Option<u64> {
+        self.smoothed.map(|s| s as u64)
+    }
+
+    pub fn update(&mut self, estimate: &Option<Estimate>) -> TimeLevel {
+        let e = match estimate {
+            Some(e) if !e.to_full => e,
+            _ => {
+                self.reset();
+                return TimeLevel::Normal;
+            }
+        };
+        // a low confidence guess neither arms nor disarms the thresholds
+        if e.confidence != Confidence::Low {
+            let smoothed = match self.smoothed {
+                Some(prev) => self.alpha * e.seconds as f64 + (1.0 - self.alpha) * prev,
+                None => e.seconds as f64,
+            };
+            self.smoothed = Some(smoothed);
+            self.critical_hits =
+                match self.critical_secs > 0 && smoothed < self.critical_secs as f64 {
+                    true => self.critical_hits + 1,
+                    false => 0,
+                };
+            self.low_hits = match self.low_secs > 0 && smoothed < self.low_secs as f64 {
+                true => self.low_hits + 1,
+                false => 0,
+            };
+        }
+        if self.critical_hits >= self.confirm {
+            TimeLevel::Critical
+        } else if self.low_hits >= self.confirm {
+            TimeLevel::Low
+        } else {
+            TimeLevel::Normal
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use crate::battery::*;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::paths;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// the full capacity is recorded at most once a day
+const RECORD_INTERVAL: u64 = 86400;
+
+pub struct BatteryInfo {
+    pub full: Option<u64>,
+    pub full_design: Option<u64>,
+    pub unit: &'static str,
+    pub cycle_count: Option<u64>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub technology: Option<String>,
+}
+
+impl BatteryInfo {
+    // batteries report either energy_* (µWh) or charge_* (µAh)
+    pub fn read(dir: &Path) -> BatteryInfo {
+        let (full, full_design, unit) = match read_u64_attr(dir, "energy_full") {
+            Some(full) => (Some(full), read_u64_attr(dir, "energy_full_design"), "Wh"),
+            None => (
+                read_u64_attr(dir, "charge_full"),
+                read_u64_attr(dir, "charge_full_design"),
+                "Ah",
+            ),
+        };
+        BatteryInfo {
+            full,
+            full_design,
+            unit,
+            cycle_count: read_u64_attr(dir, "cycle_count"),
+            manufacturer: read_string_attr(dir, "manufacturer"),
+            model_name: read_string_attr(dir, "model_name"),
+            technology: read_string_attr(dir, "technology"),
+        }
+    }
+
+    // full capacity as a percentage of the design capacity
+    pub fn health(&self) -> Option<u64> {
+        match (self.full, self.full_design) {
+            (Some(full), Some(design)) if design > 0 => Some(full * 100 / design),
+            _ => None,
+        }
+    }
+}
+
+fn health_file() -> PathBuf {
+    paths::state_dir().join("health")
+}
+
+// one line per record: "<ts> <full> <full_design> <cycle_count>"
+fn load_records(path: &Path) -> Vec<(u64, u64, u64, Option<u64>)> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return None;
+            }
+            Some((
+                fields[0].parse().ok()?,
+                fields[1].parse().ok()?,
+                fields[2].parse().ok()?,
+                fields[3].parse().ok(),
+            ))
+        })
+        .collect()
+}
+
+pub struct HealthMonitor {
+    clock: Arc<dyn Clock>,
+    alert_levels: Vec<u64>,
+    path: PathBuf,
+    last_record: u64,
+}
+
+impl HealthMonitor {
+    pub fn new(configuration: &Config, clock: Arc<dyn Clock>) -> HealthMonitor {
+        HealthMonitor {
+            clock,
+            alert_levels: configuration.health_alert_levels.clone(),
+            path: PathBuf::new(),
+            last_record: 0,
+        }
+        .with_file(&health_file())
+    }
+
+    // records to `path` instead of the health file in the state directory
+    pub fn with_file(mut self, path: &Path) -> HealthMonitor {
+        self.path = path.to_path_buf();
+        self.last_record = match load_records(path).last() {
+            Some(r) => r.0,
+            None => 0,
+        };
+        self
+    }
+
+    // records the full capacity once a day and returns the lowest wear level
+    // crossed since the previous record, the notifications replace each
+    // other. `dir` is the battery's sysfs directory, nothing is recorded
+    // without one
+    pub fn check(&mut self, dir: Option<&Path>) -> Option<u64> {
+        let now = self.clock.now();
+        if now < self.last_record.saturating_add(RECORD_INTERVAL) {
+            return None;
+        }
+        self.last_record = now;
+        let info = BatteryInfo::read(dir?);
+        let (full, design) = match (info.full, info.full_design) {
+            (Some(full), Some(design)) if design > 0 => (full, design),
+            _ => return None,
+        };
+        let previous = match load_records(&self.path).last() {
+            Some(r) if r.2 > 0 => r.1 * 100 / r.2,
+            _ => 100,
+        };
+        if let Err(e) = append_record(&self.path, now, full, design, info.cycle_count) {
+            println!("health: failed to record full capacity : {}", e);
+        }
+        let health = full * 100 / design;
+        self.alert_levels
+            .iter()
+            .filter(|&&level| previous >= level && health < level)
+            .min()
+            .copied()
+    }
+}
+
+fn append_record(
+    path: &Path,
+    now: u64,
+    full: u64,
+    design: u64,
+    cycle_count: Option<u64>,
+) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let cycles = match cycle_count {
+        Some(c) => c.to_string(),
+        None => "-".to_string(),
+    };
+    file.write_all(format!("{} {} {} {}\n", now, full, design, cycles).as_bytes())
+}
+
+fn format_capacity(value: Option<u64>, unit: &str) -> String {
+    match value {
+        Some(v) => format!("{:.2} {}", v as f64 / 1_000_000.0, unit),
+        None => "unknown".to_string(),
+    }
+}
+
+// `health`, prints the wear level and cycle count of the configured battery
+pub fn run_subcommand(configuration: &Config) -> i32 {
+    let dir = match source(configuration).dir() {
+        Some(d) => d,
+        None => {
+            println!("health: the battery has no sysfs directory to read");
+            return 1;
+        }
+    };
+    let info = BatteryInfo::read(&dir);
+    let unknown = "unknown".to_string();
+    println!(" == Battery health ({}) == ", dir.display());
+    println!(
+        "\tmanufacturer : {}",
+        info.manufacturer.as_ref().unwrap_or(&unknown)
+    );
+    println!(
+        "\tmodel_name : {}",
+        info.model_name.as_ref().unwrap_or(&unknown)
+    );
+    println!(
+        "\ttechnology : {}",
+        info.technology.as_ref().unwrap_or(&unknown)
+    );
+    println!(
+        "\tfull capacity : {}",
+        format_capacity(info.full, info.unit)
+    );
+    println!(
+        "\tdesign capacity : {}",
+        format_capacity(info.full_design, info.unit)
+    );
+    match info.health() {
+        Some(h) => {
+            println!("\thealth : {}%", h);
+            println!("\twear level : {}%", 100u64.saturating_sub(h));
+        }
+        None => println!("\thealth : unknown"),
+    }
+    match info.cycle_count {
+        Some(c) => println!("\tcycle_count : {}", c),
+        None => println!("\tcycle_count : unknown"),
+    }
+    let records = load_records(&health_file());
+    if let (Some(first), Some(last)) = (records.first(), records.last()) {
+        if first.2 > 0 && last.2 > 0 {
+            println!(
+                "\trecorded : {}% -> {}% over {} day(s)",
+                first.1 * 100 / first.2,
+                last.1 * 100 / last.2,
+                // a clock set back can leave them out of order
+                last.0.saturating_sub(first.0) / 86400
+            );
+        }
+    }
+    match info.health() {
+        Some(_) => 0,
+        None => 1,
+    }
+}
@@ -0,0 +1,283 @@
+use crate::battery::*;
+use crate::config::Config;
+use crate::paths;
+use serde_derive::Serialize;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// how often (in seconds) the history file gets compacted while recording
+const COMPACT_INTERVAL: u64 = 3600;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Sample {
+    pub timestamp: u64,
+    pub capacity: u64,
+    pub status: String,
+    pub energy_now: Option<u64>,
+    pub power_now: Option<u64>,
+    pub voltage: Option<u64>,
+}
+
+impl Sample {
+    // `dir` is the battery directory for the extra attributes, if any
+    pub fn read(dir: Option<&Path>, timestamp: u64, capacity: u64, status: &str) -> Sample {
+        let attr = |name| dir.and_then(|d| read_u64_attr(d, name));
+        Sample {
+            timestamp,
+            capacity,
+            status: status.to_string(),
+            energy_now: attr("energy_now"),
+            power_now: attr("power_now"),
+            voltage: attr("voltage_now"),
+        }
+    }
+
+    // one line per sample: "<ts> <capacity> <status> <energy> <power> <voltage>"
+    // the status is stored as its first letter and missing values as "-"
+    fn to_line(&self) -> String {
+        let status = match &self.status[..] {
+            "Charging" => "C",
+            "Discharging" => "D",
+            "Full" => "F",
+            "Not charging" => "N",
+            _ => "U",
+        };
+        format!(
+            "{} {} {} {} {} {}\n",
+            self.timestamp,
+            self.capacity,
+            status,
+            opt_to_field(self.energy_now),
+            opt_to_field(self.power_now),
+            opt_to_field(self.voltage)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Sample> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        let status = match fields[2] {
+            "C" => "Charging",
+            "D" => "Discharging",
+            "F" => "Full",
+            "N" => "Not charging",
+            _ => "Unknown",
+        };
+        Some(Sample {
+            timestamp: fields[0].parse().ok()?,
+            capacity: fields[1].parse().ok()?,
+            status: status.to_string(),
+            energy_now: fields[3].parse().ok(),
+            power_now: fields[4].parse().ok(),
+            voltage: fields[5].parse().ok(),
+        })
+    }
+}
+
+fn opt_to_field(value: Option<u64>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+pub fn unix_now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+pub struct History {
+    path: PathBuf,
+    retention: u64,
+    compact_after: u64,
+    resolution: u64,
+    last_compaction: u64,
+}
+
+impl History {
+    // Err names the setting that is out of range
+    pub fn new(configuration: &Config) -> Result<History, String> {
+        let days = |name: &str, value: u64| match value.checked_mul(86400) {
+            Some(secs) => Ok(secs),
+            None => Err(format!("{} = {} is too large", name, value)),
+        };
+        Ok(History {
+            path: paths::state_dir().join("history"),
+            retention: days(
+                "history_retention_days",
+                configuration.history_retention_days,
+            )?,
+            compact_after: days(
+                "history_compact_after_days",
+                configuration.history_compact_after_days,
+            )?,
+            resolution: configuration.history_compact_resolution,
+            last_compaction: 0,
+        })
+    }
+
+    pub fn record(&mut self, sample: &Sample) {
+        if let Some(dir) = self.path.parent() {
+            if fs::create_dir_all(dir).is_err() {
+                println!("history: could not create {}", dir.display());
+                return;
+            }
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path);
+        match file {
+            Ok(mut f) => {
+                if f.write_all(sample.to_line().as_bytes()).is_err() {
+                    println!("history: failed to write to {}", self.path.display());
+                }
+            }
+            Err(e) => {
+                println!("history: failed to open {} : {}", self.path.display(), e);
+            }
+        }
+        if sample.timestamp >= self.last_compaction + COMPACT_INTERVAL {
+            if let Err(e) = self.compact(sample.timestamp) {
+                println!("history: compaction failed : {}", e);
+            }
+            self.last_compaction = sample.timestamp;
+        }
+    }
+
+    // drop samples past the retention and thin out samples older than
+    // `compact_after` to one per `resolution` seconds
+    pub fn compact(&self, now: u64) -> std::io::Result<()> {
+        let samples = self.load(0, u64::MAX);
+        let mut kept: Vec<Sample> = Vec::new();
+        let mut last_bucket: Option<u64> = None;
+        for sample in samples {
+            let age = now.saturating_sub(sample.timestamp);
+            if self.retention > 0 && age > self.retention {
+                continue;
+            }
+            if age > self.compact_after && self.resolution > 0 {
+                let bucket = sample.timestamp / self.resolution;
+                if last_bucket == Some(bucket) {
+                    continue;
+                }
+                last_bucket = Some(bucket);
+            }
+            kept.push(sample);
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = fs::File::create(&tmp_path)?;
+        for sample in &kept {
+            tmp.write_all(sample.to_line().as_bytes())?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    pub fn load(&self, from: u64, to: u64) -> Vec<Sample> {
+        let contents = fs::read_to_string(&self.path).unwrap_or_default();
+        contents
+            .lines()
+            .filter_map(Sample::from_line)
+            .filter(|s| s.timestamp >= from && s.timestamp <= to)
+            .collect()
+    }
+}
+
+// accepts a unix timestamp, "now" or a relative time like "30m", "24h", "7d"
+fn parse_time(value: &str, now: u64) -> Option<u64> {
+    if value == "now" {
+        return Some(now);
+    }
+    if let Ok(ts) = value.parse::<u64>() {
+        return Some(ts);
+    }
+    let units = [("s", 1), ("m", 60), ("h", 3600), ("d", 86400)];
+    let (num, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| Some((value.strip_suffix(suffix)?, *unit)))?;
+    let num: u64 = num.parse().ok()?;
+    Some(now.saturating_sub(num.checked_mul(unit)?))
+}
+
+// `history [--format csv|json] [--from <time>] [--to <time>]`
+pub fn run_subcommand(configuration: &Config, args: &[String]) -> i32 {
+    let now = unix_now();
+    let mut format = "csv".to_string();
+    let mut from: u64 = 0;
+    let mut to: u64 = now;
+    let mut i = 0;
+    while i < args.len() {
+        let value = match args.get(i + 1) {
+            Some(v) => v,
+            None => {
+                println!("history: missing value for {}", args[i]);
+                return 1;
+            }
+        };
+        match &args[i][..] {
+            "--format" => format = value.clone(),
+            "--from" | "--to" => {
+                let ts = match parse_time(value, now) {
+                    Some(ts) => ts,
+                    None => {
+                        println!("history: invalid time '{}'", value);
+                        return 1;
+                    }
+                };
+                if args[i] == "--from" {
+                    from = ts;
+                } else {
+                    to = ts;
+                }
+            }
+            _ => {
+                println!("history: unknown option {}", args[i]);
+                return 1;
+            }
+        }
+        i += 2;
+    }
+
+    let samples = match History::new(configuration) {
+        Ok(h) => h.load(from, to),
+        Err(e) => {
+            println!("history: {}", e);
+            return 1;
+        }
+    };
+    match &format[..] {
+        "csv" => {
+            println!("timestamp,capacity,status,energy_now,power_now,voltage");
+            for s in &samples {
+                println!(
+                    "{},{},{},{},{},{}",
+                    s.timestamp,
+                    s.capacity,
+                    s.status,
+                    s.energy_now.map(|v| v.to_string()).unwrap_or_default(),
+                    s.power_now.map(|v| v.to_string()).unwrap_or_default(),
+                    s.voltage.map(|v| v.to_string()).unwrap_or_default()
+                );
+            }
+        }
+        "json" => match serde_json::to_string_pretty(&samples) {
+            Ok(val) => println!("{}", val),
+            Err(e) => {
+                println!("history: failed to export : {}", e);
+                return 1;
+            }
+        },
+        _ => {
+            println!("history: unknown format '{}', use csv or json", format);
+            return 1;
+        }
+    }
+    0
+}
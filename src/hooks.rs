@@ -0,0 +1,100 @@
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// pids of the hook commands that have not exited yet
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+// hook commands started, and those that could not start or exited non-zero
+static HOOK_RUNS: AtomicU64 = AtomicU64::new(0);
+static HOOK_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+// (runs, failures) since the daemon started
+pub fn hook_counts() -> (u64, u64) {
+    (
+        HOOK_RUNS.load(Ordering::Relaxed),
+        HOOK_FAILURES.load(Ordering::Relaxed),
+    )
+}
+
+pub fn run_hook(command: &str, env: &[(String, String)]) {
+    let vectorized: Vec<&str> = command.split_whitespace().collect();
+    if vectorized.is_empty() {
+        return;
+    }
+    HOOK_RUNS.fetch_add(1, Ordering::Relaxed);
+    spawn(vectorized[0], vectorized[1..].to_vec(), env, true);
+}
+
+pub fn spawn_process(proc: &str, args: Vec<&str>, env: &[(String, String)]) {
+    spawn(proc, args, env, false);
+}
+
+fn spawn(proc: &str, args: Vec<&str>, env: &[(String, String)], is_hook: bool) {
+    let mut child = match process::Command::new(proc)
+        .args(args)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Failed to run {} : {}", proc, e);
+            if is_hook {
+                HOOK_FAILURES.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+    };
+    let pid = child.id();
+    running().push(pid);
+    // reap the child in the background so it does not linger as a zombie
+    thread::spawn(move || {
+        let ok = child.wait().is_ok_and(|s| s.success());
+        if is_hook && !ok {
+            HOOK_FAILURES.fetch_add(1, Ordering::Relaxed);
+        }
+        running().retain(|p| *p != pid);
+    });
+}
+
+fn running() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn terminate(pids: &[u32]) {
+    for pid in pids {
+        println!("shutdown: sending SIGTERM to hook {}", pid);
+        // SAFETY: kill only sends a signal, the pid is one of our children
+        // that has not been reaped yet
+        unsafe {
+            libc::kill(*pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
+
+// what happens to hooks still running at shutdown: "wait" gives them
+// `timeout` seconds before SIGTERM, "kill" sends SIGTERM right away and
+// "detach" leaves them running
+pub fn finish(policy: &str, timeout: u64) {
+    let pids = running().clone();
+    if pids.is_empty() {
+        return;
+    }
+    match policy {
+        "detach" => println!("shutdown: leaving {} hook(s) running", pids.len()),
+        "kill" => terminate(&pids),
+        _ => {
+            println!(
+                "shutdown: waiting up to {}s for {} hook(s)",
+                timeout,
+                pids.len()
+            );
+            let end = Instant::now() + Duration::from_secs(timeout);
+            while !running().is_empty() && Instant::now() < end {
+                thread::sleep(Duration::from_millis(100));
+            }
+            terminate(&running().clone());
+        }
+    }
+}
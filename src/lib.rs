@@ -0,0 +1,34 @@
+pub mod alarm;
+pub mod alerts;
+pub mod audio;
+pub mod bar;
+pub mod battery;
+pub mod builtin_sounds;
+pub mod clock;
+pub mod config;
+pub mod control;
+pub mod drain;
+pub mod estimate;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod lock;
+pub mod logind;
+pub mod metrics;
+pub mod monitor;
+pub mod mqtt;
+pub mod notify;
+pub mod paths;
+pub mod peripherals;
+pub mod quiet;
+pub mod session;
+pub mod shutdown;
+pub mod simulate;
+pub mod snooze;
+pub mod state;
+pub mod systemd;
+pub mod upower;
+pub mod webhooks;
+
+pub use config::Config;
+pub use monitor::{BatteryMonitor, Event, Level, MonitorHandle, Reading};
@@ -0,0 +1,42 @@
+use crate::paths;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+pub fn lock_path() -> PathBuf {
+    paths::runtime_dir().join("batt_reminder.lock")
+}
+
+// advisory flock on a per-user file, the kernel drops it when the process
+// dies however it exits, so a leftover file after a crash is harmless
+pub struct ProgramLock {
+    _file: fs::File,
+}
+
+impl ProgramLock {
+    pub fn acquire() -> Result<ProgramLock, String> {
+        let path = lock_path();
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("could not open {} : {}", path.display(), e))?;
+        // SAFETY: the fd stays valid for as long as `file` lives
+        let locked = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if locked != 0 {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            return Err(format!(
+                "The program is already running (pid {}), lock held on {}",
+                holder.trim(),
+                path.display()
+            ));
+        }
+        // the pid is informational only, the flock is what counts
+        let _ = file.set_len(0);
+        let _ = file.write_all(format!("{}", std::process::id()).as_bytes());
+        Ok(ProgramLock { _file: file })
+    }
+}
@@ -0,0 +1,92 @@
+use std::process;
+
+pub struct UserSession {
+    pub uid: u32,
+    pub name: String,
+}
+
+// active graphical (x11/wayland/mir) sessions known to logind, one per user
+pub fn graphical_sessions() -> Vec<UserSession> {
+    let output = match process::Command::new("loginctl")
+        .args(["list-sessions", "--no-legend"])
+        .output()
+    {
+        Ok(out) => out,
+        Err(e) => {
+            println!("logind: failed to run loginctl : {}", e);
+            return vec![];
+        }
+    };
+    let mut sessions: Vec<UserSession> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let id = match line.split_whitespace().next() {
+            Some(id) => id,
+            None => continue,
+        };
+        let props = session_properties(id);
+        let prop = |name: &str| {
+            props
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+                .unwrap_or("")
+        };
+        if !matches!(prop("Type"), "x11" | "wayland" | "mir") || prop("Active") != "yes" {
+            continue;
+        }
+        let uid: u32 = match prop("User").parse() {
+            Ok(uid) => uid,
+            Err(_) => continue,
+        };
+        if sessions.iter().any(|s| s.uid == uid) {
+            continue;
+        }
+        sessions.push(UserSession {
+            uid,
+            name: prop("Name").to_string(),
+        });
+    }
+    sessions
+}
+
+fn session_properties(id: &str) -> Vec<(String, String)> {
+    let output = process::Command::new("loginctl")
+        .args([
+            "show-session",
+            id,
+            "-p",
+            "Type",
+            "-p",
+            "Active",
+            "-p",
+            "User",
+            "-p",
+            "Name",
+        ])
+        .output();
+    match output {
+        Ok(out) => String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+// runs notify-send as the user, talking to their session bus
+pub fn notify_send_as(session: &UserSession) -> process::Command {
+    let mut command = process::Command::new("runuser");
+    command.args([
+        "-u",
+        &session.name,
+        "--",
+        "env",
+        &format!(
+            "DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/{}/bus",
+            session.uid
+        ),
+        "/usr/bin/notify-send",
+    ]);
+    command
+}
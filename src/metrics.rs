@@ -0,0 +1,316 @@
+use crate::battery::*;
+use crate::config::Config;
+use crate::hooks;
+use crate::monitor::*;
+use crate::notify;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const STATUSES: [&str; 5] = ["Charging", "Discharging", "Full", "Not charging", "Unknown"];
+const LEVELS: [Level; 4] = [Level::Normal, Level::Low, Level::Critical, Level::NearDead];
+
+#[derive(Default)]
+struct Values {
+    reading: Option<Reading>,
+    level: Option<Level>,
+    cycle_count: Option<u64>,
+    // LevelCrossed events by the level they went to, in LEVELS order
+    crossings: [u64; 4],
+    read_errors: u64,
+}
+
+// the monitor readings and the daemon's counters in the Prometheus text
+// format, served on metrics_listen or written to metrics_textfile
+#[derive(Clone)]
+pub struct Metrics {
+    values: Arc<Mutex<Values>>,
+    // the battery the cycle count is read from
+    source: Arc<dyn BatterySource>,
+    textfile: Option<PathBuf>,
+    // the last textfile write failed, the error was logged once
+    textfile_failing: Arc<AtomicBool>,
+}
+
+impl Metrics {
+    // None when metrics_mode is "off" or the listener could not start
+    pub fn start(configuration: &Config, source: &Arc<dyn BatterySource>) -> Option<Metrics> {
+        let mut metrics = Metrics {
+            values: Arc::new(Mutex::new(Values::default())),
+            source: Arc::clone(source),
+            textfile: None,
+            textfile_failing: Arc::new(AtomicBool::new(false)),
+        };
+        match &configuration.metrics_mode[..] {
+            "http" => {
+                let listener = match TcpListener::bind(&configuration.metrics_listen) {
+                    Ok(l) => l,
+                    Err(e) => {
+                        println!(
+                            "metrics: could not listen on {} : {}",
+                            configuration.metrics_listen, e
+                        );
+                        return None;
+                    }
+                };
+                println!(
+                    "metrics: serving on http://{}/metrics",
+                    configuration.metrics_listen
+                );
+                let served = metrics.clone();
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        served.serve(stream);
+                    }
+                });
+            }
+            "textfile" => {
+                metrics.textfile = Some(PathBuf::from(&configuration.metrics_textfile));
+            }
+            "off" => return None,
+            other => {
+                println!("metrics: unknown metrics_mode '{}', metrics are off", other);
+                return None;
+            }
+        }
+        Some(metrics)
+    }
+
+    fn values(&self) -> std::sync::MutexGuard<'_, Values> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn update(&self, event: &Event) {
+        {
+            let mut values = self.values();
+            match event {
+                Event::Polled { reading, level, .. } => {
+                    values.reading = Some(reading.clone());
+                    values.level = Some(*level);
+                    values.cycle_count = self
+                        .source
+                        .dir()
+                        .and_then(|d| read_u64_attr(&d, "cycle_count"));
+                }
+                Event::LevelCrossed { to, .. } => {
+                    if let Some(i) = LEVELS.iter().position(|l| l == to) {
+                        values.crossings[i] += 1;
+                    }
+                }
+                Event::PluggedIn(reading) | Event::PluggedOut(reading) => {
+                    values.reading = Some(reading.clone());
+                }
+                Event::ReadError(_) => values.read_errors += 1,
+                _ => return,
+            }
+        }
+        if let Some(path) = &self.textfile {
+            // logged once rather than on every poll, e.g. with the default
+            // path on a machine without node_exporter
+            match (
+                self.write_textfile(path),
+                self.textfile_failing.load(Ordering::Relaxed),
+            ) {
+                (Err(e), false) => {
+                    println!(
+                        "metrics: could not write {} : {}, retrying quietly",
+                        path.display(),
+                        e
+                    );
+                    self.textfile_failing.store(true, Ordering::Relaxed);
+                }
+                (Ok(()), true) => {
+                    println!("metrics: writing {} again", path.display());
+                    self.textfile_failing.store(false, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // node_exporter must never read a half written file
+    fn write_textfile(&self, path: &PathBuf) -> std::io::Result<()> {
+        let tmp_path = path.with_extension("prom.tmp");
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(self.render().as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    // a stale textfile would keep reporting the last reading
+    pub fn stop(&self) {
+        if let Some(path) = &self.textfile {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn serve(&self, mut stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+        let mut request = [0; 1024];
+        let read = stream.read(&mut request).unwrap_or(0);
+        let request = String::from_utf8_lossy(&request[..read]);
+        let path = request.split_whitespace().nth(1).unwrap_or("");
+        let (status, body) = match path {
+            "/metrics" | "/" => ("200 OK", self.render()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+        let _ = stream.write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        );
+    }
+
+    fn render(&self) -> String {
+        let values = self.values();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "# HELP batt_reminder_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE batt_reminder_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "batt_reminder_{}{} {}", name, labels, value);
+            }
+        };
+        let gauge = |value: Option<f64>| match value {
+            Some(v) => vec![("".to_string(), v)],
+            None => vec![],
+        };
+
+        let sample = values.reading.as_ref().map(|r| &r.sample);
+        metric(
+            "capacity_percent",
+            "gauge",
+            "Battery capacity in percent.",
+            gauge(sample.map(|s| s.capacity as f64)),
+        );
+        // sysfs reports microwatt hours and microwatts
+        metric(
+            "energy_joules",
+            "gauge",
+            "Energy left in the battery.",
+            gauge(sample.and_then(|s| s.energy_now).map(|e| e as f64 * 0.0036)),
+        );
+        metric(
+            "power_watts",
+            "gauge",
+            "Power drawn from or into the battery.",
+            gauge(sample.and_then(|s| s.power_now).map(|p| p as f64 / 1e6)),
+        );
+        if let Some(s) = sample {
+            let mut statuses: Vec<&str> = STATUSES.to_vec();
+            if !statuses.contains(&&s.status[..]) {
+                statuses.push(&s.status);
+            }
+            metric(
+                "status",
+                "gauge",
+                "1 for the current battery status.",
+                statuses
+                    .iter()
+                    .map(|st| {
+                        (
+                            format!("{{status=\"{}\"}}", st),
+                            (*st == s.status) as u64 as f64,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+        if let Some(level) = values.level {
+            metric(
+                "level",
+                "gauge",
+                "1 for the current alert level.",
+                LEVELS
+                    .iter()
+                    .map(|l| {
+                        (
+                            format!("{{level=\"{}\"}}", l.as_str()),
+                            (*l == level) as u64 as f64,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+        if let Some(e) = values.reading.as_ref().and_then(|r| r.estimate) {
+            let kind = match e.to_full {
+                true => "to_full",
+                false => "to_empty",
+            };
+            metric(
+                "time_remaining_seconds",
+                "gauge",
+                "Estimated time to empty or to full.",
+                vec![(
+                    format!(
+                        "{{kind=\"{}\",confidence=\"{}\"}}",
+                        kind,
+                        e.confidence.as_str()
+                    ),
+                    e.seconds as f64,
+                )],
+            );
+        }
+        metric(
+            "cycle_count",
+            "gauge",
+            "Charge cycles reported by the battery.",
+            gauge(values.cycle_count.map(|c| c as f64)),
+        );
+        metric(
+            "last_poll_timestamp_seconds",
+            "gauge",
+            "Unix time of the last reading.",
+            gauge(sample.map(|s| s.timestamp as f64)),
+        );
+        metric(
+            "threshold_crossings_total",
+            "counter",
+            "Level changes by the level entered.",
+            LEVELS
+                .iter()
+                .zip(values.crossings)
+                .map(|(l, n)| (format!("{{level=\"{}\"}}", l.as_str()), n as f64))
+                .collect(),
+        );
+        metric(
+            "read_errors_total",
+            "counter",
+            "Failed battery reads.",
+            gauge(Some(values.read_errors as f64)),
+        );
+        let (runs, failures) = hooks::hook_counts();
+        metric(
+            "hook_runs_total",
+            "counter",
+            "Hook commands started.",
+            gauge(Some(runs as f64)),
+        );
+        metric(
+            "hook_failures_total",
+            "counter",
+            "Hook commands that failed to start or exited non-zero.",
+            gauge(Some(failures as f64)),
+        );
+        metric(
+            "notifications_total",
+            "counter",
+            "Notifications sent.",
+            gauge(Some(notify::notifications_sent() as f64)),
+        );
+        out
+    }
+}
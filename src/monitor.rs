@@ -0,0 +1,448 @@
+use crate::battery::*;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::drain::{DrainDetector, DrainReport};
+use crate::estimate::*;
+use crate::health::HealthMonitor;
+use crate::history::Sample;
+use crate::peripherals::{PeripheralAlert, PeripheralMonitor};
+use crate::shutdown::Shutdown;
+use crate::systemd;
+use std::cell::Cell;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const NEAR_DED: u64 = 10;
+
+// where the battery stands while discharging, anything else is Normal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Normal,
+    Low,
+    Critical,
+    NearDead,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Normal => "normal",
+            Level::Low => "low",
+            Level::Critical => "critical",
+            Level::NearDead => "near_dead",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub sample: Sample,
+    pub estimate: Option<Estimate>,
+}
+
+impl Reading {
+    pub fn capacity(&self) -> u64 {
+        self.sample.capacity
+    }
+
+    pub fn status(&self) -> &str {
+        &self.sample.status
+    }
+
+    // ", 1h 05m remaining" or nothing without an estimate
+    pub fn time_left(&self) -> String {
+        match &self.estimate {
+            Some(e) => format!(", {}", e.describe()),
+            None => "".to_string(),
+        }
+    }
+
+    pub fn hook_env(&self) -> Vec<(String, String)> {
+        hook_env(self.capacity(), self.status(), &self.estimate)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    // every poll of the level loop, whatever the level. `smoothed` is the
+    // time to empty in seconds the time thresholds go by
+    Polled {
+        reading: Reading,
+        level: Level,
+        smoothed: Option<u64>,
+    },
+    LevelCrossed {
+        from: Level,
+        to: Level,
+        reading: Reading,
+    },
+    PluggedIn(Reading),
+    PluggedOut(Reading),
+    ReadError(String),
+    DrainSpike(DrainReport),
+    // percent of the design capacity the full capacity dropped below
+    HealthDropped(u64),
+    // a mouse, keyboard or headset ran low, already rate limited
+    PeripheralLow(PeripheralAlert),
+}
+
+// the capacity, time and drain logic behind the notifications, one `poll`
+// per iteration of the monitor loop
+pub struct LevelMonitor {
+    configuration: Config,
+    clock: Arc<dyn Clock>,
+    source: Arc<dyn BatterySource>,
+    estimator: Estimator,
+    time_thresholds: TimeThresholds,
+    health: Option<HealthMonitor>,
+    drain: Option<DrainDetector>,
+    level: Level,
+}
+
+impl LevelMonitor {
+    pub fn new(
+        configuration: &Config,
+        clock: Arc<dyn Clock>,
+        source: Arc<dyn BatterySource>,
+    ) -> LevelMonitor {
+        LevelMonitor {
+            configuration: configuration.clone(),
+            clock: Arc::clone(&clock),
+            source,
+            estimator: Estimator::new(configuration.estimate_window),
+            time_thresholds: TimeThresholds::new(
+                configuration.time_low_minutes,
+                configuration.time_critical_minutes,
+                configuration.time_threshold_confirm,
+            ),
+            health: match configuration.health_check_enable {
+                true => Some(HealthMonitor::new(configuration, Arc::clone(&clock))),
+                false => None,
+            },
+            drain: match configuration.drain_detect_enable {
+                true => Some(DrainDetector::new(configuration)),
+                false => None,
+            },
+            level: Level::Normal,
+        }
+    }
+
+    // critical is checked first, so near dead only shows up when both
+    // thresholds are below NEAR_DED
+    fn level_for(&self, capacity: u64, time_level: TimeLevel) -> Level {
+        if capacity < self.configuration.battery_critical || time_level == TimeLevel::Critical {
+            Level::Critical
+        } else if capacity < self.configuration.battery_low || time_level == TimeLevel::Low {
+            Level::Low
+        } else if capacity < NEAR_DED {
+            Level::NearDead
+        } else {
+            Level::Normal
+        }
+    }
+
+    // reads the battery once, returns what happened and how long to wait
+    // before the next poll
+    pub fn poll(&mut self) -> (Vec<Event>, Duration) {
+        let mut events = Vec::new();
+        let dir = self.source.dir();
+        if let Some(h) = &mut self.health {
+            if let Some(level) = h.check(dir.as_deref()) {
+                events.push(Event::HealthDropped(level));
+            }
+        }
+
+        let read = self
+            .source
+            .status()
+            .and_then(|status| Ok((self.source.capacity()?, status)));
+        let (capacity, status) = match read {
+            Ok(r) => r,
+            Err(e) => {
+                events.push(Event::ReadError(e));
+                return (events, Duration::from_secs(5));
+            }
+        };
+        let sample = Sample::read(dir.as_deref(), self.clock.now(), capacity, &status);
+        self.estimator.push(sample.timestamp, capacity, &status);
+        let estimate = self
+            .source
+            .estimate(&status)
+            .or_else(|| self.estimator.estimate(dir.as_deref(), &status, capacity));
+        let time_level = self.time_thresholds.update(&estimate);
+        if let Some(d) = &mut self.drain {
+            match &status[..] {
+                "Discharging" => {
+                    if let Some(report) =
+                        d.update(dir.as_deref(), &self.estimator, sample.timestamp)
+                    {
+                        events.push(Event::DrainSpike(report));
+                    }
+                }
+                _ => d.reset(),
+            }
+        }
+
+        let level = match &status[..] {
+            "Discharging" => self.level_for(capacity, time_level),
+            _ => Level::Normal,
+        };
+        let reading = Reading { sample, estimate };
+        if level != self.level {
+            events.push(Event::LevelCrossed {
+                from: self.level,
+                to: level,
+                reading: reading.clone(),
+            });
+            self.level = level;
+        }
+        let sleep = match (&status[..], level) {
+            ("Charging", _) | ("Full", _) => self.configuration.normal_sleep_time,
+            ("Discharging", Level::Critical) => self.configuration.critical_sleep_time,
+            ("Discharging", Level::Low) => self.configuration.fast_sleep_time,
+            ("Discharging", Level::NearDead) => 0,
+            ("Discharging", Level::Normal) => self.configuration.normal_sleep_time,
+            _ => 0,
+        };
+        events.push(Event::Polled {
+            reading,
+            level,
+            smoothed: self.time_thresholds.smoothed_seconds(),
+        });
+        (events, Duration::from_secs(sleep + 5))
+    }
+}
+
+// notices the charger being connected or removed between two polls
+pub struct PlugMonitor {
+    configuration: Config,
+    clock: Arc<dyn Clock>,
+    source: Arc<dyn BatterySource>,
+    estimator: Estimator,
+    previous: Option<String>,
+}
+
+impl PlugMonitor {
+    pub fn new(
+        configuration: &Config,
+        clock: Arc<dyn Clock>,
+        source: Arc<dyn BatterySource>,
+    ) -> PlugMonitor {
+        PlugMonitor {
+            configuration: configuration.clone(),
+            clock,
+            source,
+            estimator: Estimator::new(configuration.estimate_window),
+            previous: None,
+        }
+    }
+
+    fn reading(&self, status: &str) -> Result<Reading, String> {
+        let capacity = self.source.capacity()?;
+        let dir = self.source.dir();
+        let sample = Sample::read(dir.as_deref(), self.clock.now(), capacity, status);
+        let estimate = self
+            .source
+            .estimate(status)
+            .or_else(|| self.estimator.estimate(dir.as_deref(), status, capacity));
+        Ok(Reading { sample, estimate })
+    }
+
+    pub fn poll(&mut self) -> (Vec<Event>, Duration) {
+        let interval = Duration::from_secs(self.configuration.plug_in_check_interval);
+        let status = match self.source.status() {
+            Ok(s) => s,
+            Err(e) => return (vec![Event::ReadError(e)], interval),
+        };
+        let previous = self.previous.replace(status.clone());
+        let was_discharging = match &previous {
+            Some(p) => p == "Discharging",
+            None => return (vec![], interval),
+        };
+        let event = match (was_discharging, &status[..] == "Discharging") {
+            (true, false) => self.reading(&status).map(Event::PluggedIn),
+            (false, true) => self.reading(&status).map(Event::PluggedOut),
+            // on AC with nothing new, no need to check as often
+            (false, false) => return (vec![], interval + Duration::from_secs(5)),
+            (true, true) => return (vec![], interval),
+        };
+        match event {
+            Ok(e) => (vec![e], interval),
+            Err(e) => (vec![Event::ReadError(e)], interval),
+        }
+    }
+}
+
+// runs the level loop and, with enable_plug_in_check and
+// peripheral_check_enable, the plug and peripheral loops in their own threads
+// and sends their events to every subscriber
+pub struct BatteryMonitor {
+    configuration: Config,
+    clock: Arc<dyn Clock>,
+    source: Arc<dyn BatterySource>,
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl BatteryMonitor {
+    // reads the configured backend on the system clock
+    pub fn new(configuration: &Config) -> BatteryMonitor {
+        BatteryMonitor {
+            configuration: configuration.clone(),
+            clock: Arc::new(SystemClock),
+            source: source(configuration),
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> BatteryMonitor {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_source(mut self, source: Arc<dyn BatterySource>) -> BatteryMonitor {
+        self.source = source;
+        self
+    }
+
+    // the battery the loops read, shared with whatever else needs it
+    pub fn source(&self) -> Arc<dyn BatterySource> {
+        Arc::clone(&self.source)
+    }
+
+    // the receiver is closed once the monitor has stopped
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn start(self, shutdown: &Shutdown) -> MonitorHandle {
+        let mut threads = Vec::new();
+        let mut level = LevelMonitor::new(
+            &self.configuration,
+            Arc::clone(&self.clock),
+            Arc::clone(&self.source),
+        );
+        // a source that reports its changes wakes the level loop at once
+        let changes = self.source.changes();
+        let subscribers = self.subscribers.clone();
+        let clock = Arc::clone(&self.clock);
+        let level_shutdown = shutdown.clone();
+        threads.push(spawn_loop("monitor-level", move || loop {
+            let (events, sleep) = level.poll();
+            publish(&subscribers, events);
+            let changed = Cell::new(false);
+            let wait = |d| {
+                match (&changes, changed.get()) {
+                    // the rest of the sleep is skipped, the watchdog is
+                    // still pinged on the way out
+                    (Some(_), true) => {}
+                    (Some(c), false) => changed.set(wait_for_change(c, d, &level_shutdown)),
+                    (None, _) => return clock.sleep(d, &level_shutdown),
+                }
+                !level_shutdown.is_triggered()
+            };
+            if !systemd::watchdog_sleep(sleep, &wait) {
+                return;
+            }
+        }));
+        if self.configuration.peripheral_check_enable {
+            let mut peripherals =
+                PeripheralMonitor::new(&self.configuration, Arc::clone(&self.clock));
+            let subscribers = self.subscribers.clone();
+            let clock = Arc::clone(&self.clock);
+            let peripheral_shutdown = shutdown.clone();
+            threads.push(spawn_loop("monitor-peripherals", move || loop {
+                let (events, sleep) = peripherals.poll();
+                publish(&subscribers, events);
+                if !clock.sleep(sleep, &peripheral_shutdown) {
+                    return;
+                }
+            }));
+        }
+        if self.configuration.enable_plug_in_check {
+            println!(
+                "check_charging: this thread will check if the battery is Discharging every {} sec(s)...",
+                self.configuration.plug_in_check_interval
+            );
+            // a source that reports its changes wakes the plug loop at once
+            let changes = self.source.changes();
+            let mut plug =
+                PlugMonitor::new(&self.configuration, Arc::clone(&self.clock), self.source);
+            let subscribers = self.subscribers;
+            let clock = self.clock;
+            let plug_shutdown = shutdown.clone();
+            threads.push(spawn_loop("monitor-plug", move || loop {
+                let (events, sleep) = plug.poll();
+                publish(&subscribers, events);
+                let awake = match &changes {
+                    Some(c) => {
+                        wait_for_change(c, sleep, &plug_shutdown);
+                        !plug_shutdown.is_triggered()
+                    }
+                    None => clock.sleep(sleep, &plug_shutdown),
+                };
+                if !awake {
+                    return;
+                }
+            }));
+        }
+        MonitorHandle { threads }
+    }
+}
+
+// waits up to `duration`, until the source changed or until `shutdown` was
+// triggered, true if the source changed
+fn wait_for_change(changes: &Receiver<()>, duration: Duration, shutdown: &Shutdown) -> bool {
+    let step = Duration::from_secs(1);
+    let mut left = duration;
+    while !left.is_zero() && !shutdown.is_triggered() {
+        match changes.recv_timeout(left.min(step)) {
+            Ok(()) => {
+                // one poll covers every change that came in meanwhile
+                changes.try_iter().count();
+                return true;
+            }
+            Err(RecvTimeoutError::Timeout) => left = left.saturating_sub(step),
+            Err(RecvTimeoutError::Disconnected) => {
+                shutdown.sleep(left);
+                return false;
+            }
+        }
+    }
+    false
+}
+
+// named so the daemon's panic hook can tell a monitor loop died
+fn spawn_loop<F: FnOnce() + Send + 'static>(name: &str, body: F) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(body)
+        .expect("could not start a monitor thread")
+}
+
+fn publish(subscribers: &[Sender<Event>], events: Vec<Event>) {
+    for event in events {
+        for subscriber in subscribers {
+            // a subscriber that went away just misses the event
+            let _ = subscriber.send(event.clone());
+        }
+    }
+}
+
+pub struct MonitorHandle {
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl MonitorHandle {
+    // waits for the monitor threads, false if one of them panicked
+    pub fn join(self) -> bool {
+        let mut ok = true;
+        for t in self.threads {
+            ok &= t.join().is_ok();
+        }
+        ok
+    }
+}
@@ -0,0 +1,539 @@
+use crate::config::Config;
+use crate::history::unix_now;
+use crate::monitor::*;
+use crate::session::hostname;
+use crate::snooze::Snooze;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// MQTT 3.1.1 control packets, the first byte with the flags the spec fixes
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+// seconds between reconnects, doubling up to MAX_RECONNECT
+const MIN_RECONNECT: u64 = 5;
+const MAX_RECONNECT: u64 = 300;
+// a write the broker does not take within this many seconds drops the
+// connection
+const WRITE_TIMEOUT: u64 = 10;
+// the commands we subscribe to are short, anything bigger is a broken or
+// hostile broker
+const MAX_PACKET: usize = 64 * 1024;
+// QoS 1 publishes the broker has not acknowledged yet, the oldest are given
+// up on past this many
+const MAX_PENDING: usize = 100;
+// the DUP flag of a PUBLISH sent again
+const DUP: u8 = 0x08;
+
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            return;
+        }
+    }
+}
+
+fn push_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn packet(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![first];
+    push_length(&mut out, body.len());
+    out.extend_from_slice(body);
+    out
+}
+
+// (first byte, rest of the packet)
+fn read_packet(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut first = [0u8; 1];
+    stream.read_exact(&mut first)?;
+    let mut len = 0usize;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid remaining length",
+            ));
+        }
+    }
+    if len > MAX_PACKET {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} byte packet is too big", len),
+        ));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok((first[0], body))
+}
+
+// (topic, payload) of a PUBLISH, with the packet id when it needs a PUBACK
+fn parse_publish(first: u8, body: &[u8]) -> Option<(String, String, Option<u16>)> {
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = String::from_utf8_lossy(body.get(2..2 + len)?).to_string();
+    let mut rest = 2 + len;
+    let id = match (first >> 1) & 3 {
+        0 => None,
+        _ => {
+            let id = u16::from_be_bytes([*body.get(rest)?, *body.get(rest + 1)?]);
+            rest += 2;
+            Some(id)
+        }
+    };
+    let payload = String::from_utf8_lossy(body.get(rest..)?).to_string();
+    Some((topic, payload, id))
+}
+
+// (packet id, PUBLISH) waiting for a PUBACK
+type Pending = VecDeque<(u16, Vec<u8>)>;
+
+// what the writer thread sends to the broker
+enum Outgoing {
+    Packet(Vec<u8>),
+    // closes the connection, then answers on the sender
+    Close(Sender<()>),
+}
+
+// publishes the battery state under mqtt_topic_prefix with Home Assistant
+// discovery, and runs the commands sent to <prefix>/command. Everything goes
+// out at mqtt_qos through a writer thread, so a stalled broker never holds
+// up the caller
+#[derive(Clone)]
+pub struct Mqtt {
+    configuration: Config,
+    prefix: String,
+    host: String,
+    snooze: Snooze,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    outgoing: Sender<Outgoing>,
+    packet_id: Arc<AtomicU16>,
+    // sent again after a reconnect
+    pending: Arc<Mutex<Pending>>,
+    // the last payload of each topic, published again after a reconnect
+    values: Arc<Mutex<BTreeMap<String, String>>>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl Mqtt {
+    // None without an mqtt_broker or with an mqtt_qos other than 0 or 1.
+    // `handler` answers the command topic the same way the control socket
+    // does
+    pub fn start<F>(configuration: &Config, snooze: &Snooze, handler: F) -> Option<Mqtt>
+    where
+        F: Fn(&str) -> String + Send + 'static,
+    {
+        if configuration.mqtt_broker.is_empty() {
+            return None;
+        }
+        if configuration.mqtt_qos > 1 {
+            println!(
+                "mqtt: mqtt_qos = {} is not supported, only 0 and 1, not publishing",
+                configuration.mqtt_qos
+            );
+            return None;
+        }
+        let host = hostname();
+        let (outgoing, queued) = channel();
+        let mqtt = Mqtt {
+            configuration: configuration.clone(),
+            prefix: configuration.mqtt_topic_prefix.replace("{host}", &host),
+            host,
+            snooze: snooze.clone(),
+            connection: Arc::new(Mutex::new(None)),
+            outgoing,
+            packet_id: Arc::new(AtomicU16::new(1)),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            values: Arc::new(Mutex::new(BTreeMap::new())),
+            stopping: Arc::new(AtomicBool::new(false)),
+        };
+        let writer = mqtt.clone();
+        thread::spawn(move || writer.write(queued));
+        let client = mqtt.clone();
+        thread::spawn(move || client.run(handler));
+        Some(mqtt)
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Option<TcpStream>> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // the writer thread. A failed write drops the connection, the client
+    // thread reconnects; packets queued meanwhile are lost unless they wait
+    // for a PUBACK
+    fn write(&self, queued: Receiver<Outgoing>) {
+        for outgoing in queued {
+            let mut connection = self.connection();
+            match outgoing {
+                Outgoing::Packet(bytes) => {
+                    if let Some(stream) = connection.as_mut() {
+                        if let Err(e) = stream.write_all(&bytes) {
+                            println!("mqtt: connection lost : {}", e);
+                            let _ = stream.shutdown(std::net::Shutdown::Both);
+                            *connection = None;
+                        }
+                    }
+                }
+                Outgoing::Close(done) => {
+                    if let Some(stream) = connection.take() {
+                        let _ = stream.shutdown(std::net::Shutdown::Both);
+                    }
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn send(&self, bytes: Vec<u8>) {
+        let _ = self.outgoing.send(Outgoing::Packet(bytes));
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn publish(&self, topic: &str, payload: &str, retain: bool) {
+        let qos = self.configuration.mqtt_qos as u8;
+        let mut body = Vec::new();
+        push_str(&mut body, topic);
+        let id = match qos {
+            0 => None,
+            _ => {
+                // 0 is not a valid packet id
+                let mut id = self.packet_id.fetch_add(1, Ordering::Relaxed);
+                if id == 0 {
+                    id = self.packet_id.fetch_add(1, Ordering::Relaxed);
+                }
+                body.extend_from_slice(&id.to_be_bytes());
+                Some(id)
+            }
+        };
+        body.extend_from_slice(payload.as_bytes());
+        let bytes = packet(PUBLISH | qos << 1 | retain as u8, &body);
+        if let Some(id) = id {
+            let mut pending = self.pending();
+            if pending.len() >= MAX_PENDING {
+                pending.pop_front();
+            }
+            pending.push_back((id, bytes.clone()));
+        }
+        self.send(bytes);
+    }
+
+    fn acknowledged(&self, body: &[u8]) {
+        if let [high, low] = body {
+            let id = u16::from_be_bytes([*high, *low]);
+            self.pending().retain(|(pending, _)| *pending != id);
+        }
+    }
+
+    // publishes `<prefix>/<name>` when the payload changed
+    fn set(&self, name: &str, payload: String) {
+        let topic = self.topic(name);
+        {
+            let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+            if values.get(&topic) == Some(&payload) {
+                return;
+            }
+            values.insert(topic.clone(), payload.clone());
+        }
+        self.publish(&topic, &payload, self.configuration.mqtt_retain);
+    }
+
+    pub fn update(&self, event: &Event) {
+        match event {
+            Event::Polled { reading, level, .. } => {
+                self.set_reading(reading);
+                self.set("level", level.as_str().to_string());
+            }
+            Event::PluggedIn(reading) | Event::PluggedOut(reading) => self.set_reading(reading),
+            _ => {}
+        }
+        // a snooze running out shows up with the next event
+        self.set("snooze", self.snooze.describe(unix_now()).to_string());
+    }
+
+    fn set_reading(&self, reading: &Reading) {
+        self.set("capacity", reading.capacity().to_string());
+        self.set("status", reading.status().to_string());
+        let time = match &reading.estimate {
+            Some(e) => e.seconds.to_string(),
+            None => "".to_string(),
+        };
+        self.set("time_remaining", time);
+    }
+
+    // marks the daemon offline and closes the connection, waiting a little
+    // for what is still queued
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        self.publish(&self.topic("available"), "offline", true);
+        self.send(packet(DISCONNECT, &[]));
+        let (done, closed) = channel();
+        if self.outgoing.send(Outgoing::Close(done)).is_ok() {
+            let _ = closed.recv_timeout(Duration::from_secs(WRITE_TIMEOUT + 1));
+        }
+    }
+
+    fn run<F>(&self, handler: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut backoff = MIN_RECONNECT;
+        while !self.stopping.load(Ordering::Relaxed) {
+            match self.connect() {
+                Ok(stream) => {
+                    backoff = MIN_RECONNECT;
+                    self.serve(stream, &handler);
+                }
+                Err(e) => println!(
+                    "mqtt: could not connect to {} : {}",
+                    self.configuration.mqtt_broker, e
+                ),
+            }
+            self.connection().take();
+            if self.stopping.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(Duration::from_secs(backoff));
+            backoff = (backoff * 2).min(MAX_RECONNECT);
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        let configuration = &self.configuration;
+        let mut stream =
+            TcpStream::connect(&configuration.mqtt_broker).map_err(|e| e.to_string())?;
+        let client_id = match configuration.mqtt_client_id.is_empty() {
+            true => format!("batt_reminder-{}", self.host),
+            false => configuration.mqtt_client_id.clone(),
+        };
+        // clean session, a retained "offline" at mqtt_qos as the last will
+        let mut flags = 0x02 | 0x04 | 0x20 | (configuration.mqtt_qos as u8) << 3;
+        if !configuration.mqtt_username.is_empty() {
+            flags |= 0x80;
+            if !configuration.mqtt_password.is_empty() {
+                flags |= 0x40;
+            }
+        }
+        let mut body = Vec::new();
+        push_str(&mut body, "MQTT");
+        body.push(4);
+        body.push(flags);
+        body.extend_from_slice(&(configuration.mqtt_keepalive as u16).to_be_bytes());
+        push_str(&mut body, &client_id);
+        push_str(&mut body, &self.topic("available"));
+        push_str(&mut body, "offline");
+        if flags & 0x80 != 0 {
+            push_str(&mut body, &configuration.mqtt_username);
+        }
+        if flags & 0x40 != 0 {
+            push_str(&mut body, &configuration.mqtt_password);
+        }
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT)));
+        stream
+            .write_all(&packet(CONNECT, &body))
+            .map_err(|e| e.to_string())?;
+        match read_packet(&mut stream) {
+            Ok((CONNACK, body)) if body.get(1) == Some(&0) => {}
+            Ok((CONNACK, body)) => {
+                return Err(format!(
+                    "refused with code {}",
+                    body.get(1).copied().unwrap_or(255)
+                ))
+            }
+            Ok((first, _)) => return Err(format!("unexpected packet 0x{:02x}", first)),
+            Err(e) => return Err(e.to_string()),
+        }
+        println!(
+            "mqtt: connected to {} as {}",
+            configuration.mqtt_broker, client_id
+        );
+        Ok(stream)
+    }
+
+    fn serve<F>(&self, mut stream: TcpStream, handler: &F)
+    where
+        F: Fn(&str) -> String,
+    {
+        let keepalive = self.configuration.mqtt_keepalive.max(2);
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(keepalive / 2)));
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT)));
+        match stream.try_clone() {
+            Ok(writer) => *self.connection() = Some(writer),
+            Err(e) => {
+                println!("mqtt: {}", e);
+                return;
+            }
+        }
+        let command_topic = self.topic("command");
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u16.to_be_bytes());
+        push_str(&mut body, &command_topic);
+        body.push(self.configuration.mqtt_qos as u8);
+        self.send(packet(SUBSCRIBE, &body));
+        // the session is clean, what the last broker did not acknowledge is
+        // sent again
+        let pending: Vec<Vec<u8>> = self.pending().iter().map(|(_, p)| p.clone()).collect();
+        for mut bytes in pending {
+            bytes[0] |= DUP;
+            self.send(bytes);
+        }
+        self.publish(&self.topic("available"), "online", true);
+        self.discovery();
+        let values = self
+            .values
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for (topic, payload) in values {
+            self.publish(&topic, &payload, self.configuration.mqtt_retain);
+        }
+
+        let mut waiting_for_pong = false;
+        loop {
+            match read_packet(&mut stream) {
+                Ok((first, body)) if first & 0xf0 == PUBLISH => {
+                    let (topic, payload, id) = match parse_publish(first, &body) {
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    if let Some(id) = id {
+                        self.send(packet(PUBACK, &id.to_be_bytes()));
+                    }
+                    if topic != command_topic {
+                        continue;
+                    }
+                    let reply = handler(payload.trim());
+                    println!("mqtt: command '{}' : {}", payload.trim(), reply);
+                    self.publish(&self.topic("command/reply"), &reply, false);
+                    self.set("snooze", self.snooze.describe(unix_now()).to_string());
+                }
+                Ok((PUBACK, body)) => self.acknowledged(&body),
+                Ok((PINGRESP, _)) => waiting_for_pong = false,
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if waiting_for_pong {
+                        println!("mqtt: the broker stopped answering");
+                        return;
+                    }
+                    waiting_for_pong = true;
+                    self.send(packet(PINGREQ, &[]));
+                }
+                Err(e) => {
+                    if !self.stopping.load(Ordering::Relaxed) {
+                        println!("mqtt: connection lost : {}", e);
+                    }
+                    return;
+                }
+            }
+            if self.connection().is_none() {
+                return;
+            }
+        }
+    }
+
+    // retained Home Assistant discovery configs for every sensor and
+    // command, one device per machine
+    fn discovery(&self) {
+        let discovery_prefix = &self.configuration.mqtt_discovery_prefix;
+        if discovery_prefix.is_empty() {
+            return;
+        }
+        let node: String = format!("batt_reminder_{}", self.host)
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c,
+                false => '_',
+            })
+            .collect();
+        let device = json!({
+            "identifiers": [node],
+            "name": format!("{} battery", self.host),
+            "manufacturer": "rust-batt-reminder",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let sensors: [(&str, &str, Value); 5] = [
+            (
+                "capacity",
+                "Battery",
+                json!({"device_class": "battery", "unit_of_measurement": "%", "state_class": "measurement"}),
+            ),
+            ("status", "Battery status", json!({})),
+            ("level", "Battery level", json!({})),
+            (
+                "time_remaining",
+                "Battery time remaining",
+                json!({"device_class": "duration", "unit_of_measurement": "s"}),
+            ),
+            ("snooze", "Battery reminders", json!({})),
+        ];
+        for (name, title, mut config) in sensors {
+            config["name"] = json!(title);
+            config["unique_id"] = json!(format!("{}_{}", node, name));
+            config["state_topic"] = json!(self.topic(name));
+            config["availability_topic"] = json!(self.topic("available"));
+            config["device"] = device.clone();
+            self.publish(
+                &format!("{}/sensor/{}/{}/config", discovery_prefix, node, name),
+                &config.to_string(),
+                true,
+            );
+        }
+        let buttons = [
+            ("snooze_30", "Snooze battery reminders 30 min", "snooze 30"),
+            ("pause", "Pause battery reminders", "pause"),
+            ("resume", "Resume battery reminders", "resume"),
+            ("ack", "Acknowledge battery alarm", "ack"),
+        ];
+        for (name, title, command) in buttons {
+            let config = json!({
+                "name": title,
+                "unique_id": format!("{}_{}", node, name),
+                "command_topic": self.topic("command"),
+                "payload_press": command,
+                "availability_topic": self.topic("available"),
+                "device": device,
+            });
+            self.publish(
+                &format!("{}/button/{}/{}/config", discovery_prefix, node, name),
+                &config.to_string(),
+                true,
+            );
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::logind;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+// set by --system, notifications then go to every active graphical session
+static SYSTEM_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_system_mode(enabled: bool) {
+    SYSTEM_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn system_mode() -> bool {
+    SYSTEM_MODE.load(Ordering::Relaxed)
+}
+
+// notifications sent since the daemon started, however many sessions got each
+static NOTIFICATIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn notifications_sent() -> u64 {
+    NOTIFICATIONS.load(Ordering::Relaxed)
+}
+
+// one notify-send per target: our own session, or in system mode every user
+// with an active graphical session
+fn notify_send_commands() -> Vec<process::Command> {
+    match system_mode() {
+        false => vec![process::Command::new("/usr/bin/notify-send")],
+        true => logind::graphical_sessions()
+            .iter()
+            .map(logind::notify_send_as)
+            .collect(),
+    }
+}
+
+pub fn spawn_notif(string: String, progress_bar_value: u64, urgency: &str) {
+    NOTIFICATIONS.fetch_add(1, Ordering::Relaxed);
+    for mut command in notify_send_commands() {
+        command
+            .arg("--app-name=batt-reminder")
+            .arg("--replace-id=2592");
+        if progress_bar_value != 0 {
+            command.arg(format!("--hint=int:value:{}", progress_bar_value));
+        }
+        let child = command
+            .arg(format!("--urgency={}", urgency))
+            .arg("--expire-time= 10000")
+            .arg(&string)
+            .spawn();
+        match child {
+            // waited for on the side so the caller never blocks on it
+            Ok(mut c) => {
+                thread::spawn(move || c.wait());
+            }
+            Err(e) => println!("Failed to send the notification : {}", e),
+        }
+    }
+}
+
+// notification with a single action button, returns true once it was
+// clicked in any of the sessions
+pub fn spawn_notif_action(string: String, label: &str, urgency: &str) -> bool {
+    NOTIFICATIONS.fetch_add(1, Ordering::Relaxed);
+    let (clicked, answers) = std::sync::mpsc::channel();
+    for mut command in notify_send_commands() {
+        command
+            .arg("--app-name=batt-reminder")
+            .arg(format!("--urgency={}", urgency))
+            .arg(format!("--action=act={}", label))
+            .arg("--wait")
+            .arg(&string);
+        let clicked = clicked.clone();
+        thread::spawn(move || {
+            let answer = match command.output() {
+                Ok(out) => String::from_utf8_lossy(&out.stdout).trim() == "act",
+                Err(_) => false,
+            };
+            let _ = clicked.send(answer);
+        });
+    }
+    drop(clicked);
+    answers.iter().any(|a| a)
+}
@@ -0,0 +1,83 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+fn home_dir() -> PathBuf {
+    match env::var("HOME") {
+        Ok(val) => PathBuf::from(val),
+        Err(_) => PathBuf::from("/tmp"),
+    }
+}
+
+// $XDG_STATE_HOME/batt_reminder, falling back to ~/.local/state/batt_reminder
+pub fn state_dir() -> PathBuf {
+    let base = match env::var("XDG_STATE_HOME") {
+        Ok(val) if !val.is_empty() => PathBuf::from(val),
+        _ => home_dir().join(".local/state"),
+    };
+    base.join("batt_reminder")
+}
+
+// $XDG_RUNTIME_DIR, falling back to /run/batt_reminder for root (the system
+// daemon) or a private /tmp/batt_reminder-<uid> directory for other users
+pub fn runtime_dir() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(val) if !val.is_empty() => PathBuf::from(val),
+        _ => {
+            static FALLBACK: OnceLock<PathBuf> = OnceLock::new();
+            FALLBACK.get_or_init(fallback_runtime_dir).clone()
+        }
+    }
+}
+
+fn fallback_runtime_dir() -> PathBuf {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let dir = match uid {
+        0 => PathBuf::from("/run/batt_reminder"),
+        _ => PathBuf::from(format!("/tmp/batt_reminder-{}", uid)),
+    };
+    match private_dir(&dir, uid) {
+        Ok(()) => dir,
+        Err(e) => {
+            // someone else may have made it first to get at our socket and
+            // lock, the state directory is ours alone
+            let own = state_dir().join("run");
+            println!(
+                "paths: not using {} ({}), using {} instead",
+                dir.display(),
+                e,
+                own.display()
+            );
+            let _ = fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&own);
+            own
+        }
+    }
+}
+
+// creates `dir` unless it exists, Ok only for a real directory owned by `uid`
+// that nobody else can get into
+fn private_dir(dir: &Path, uid: u32) -> Result<(), String> {
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(dir) {
+        if e.kind() != ErrorKind::AlreadyExists {
+            return Err(e.to_string());
+        }
+    }
+    let metadata = fs::symlink_metadata(dir).map_err(|e| e.to_string())?;
+    if !metadata.file_type().is_dir() {
+        return Err("not a directory".to_string());
+    }
+    if metadata.uid() != uid {
+        return Err(format!("owned by uid {}", metadata.uid()));
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        return Err(format!("mode {:o}", metadata.mode() & 0o777));
+    }
+    Ok(())
+}
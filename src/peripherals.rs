@@ -0,0 +1,239 @@
+use crate::battery::*;
+use crate::clock::Clock;
+use crate::config::{Config, PeripheralConfig};
+use crate::monitor::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// a wireless mouse, keyboard or headset under /sys/class/power_supply
+#[derive(Clone, Debug)]
+pub struct Peripheral {
+    // the power_supply name, e.g. hid-00:11:22:33:44:55-battery
+    pub name: String,
+    pub model: Option<String>,
+    // serial number when the driver has one, the rate limit follows it
+    // across reconnects that renumber the power_supply
+    pub serial: Option<String>,
+    pub capacity: Option<u64>,
+    // Critical/Low/Normal/High/Full for devices that report no percentage
+    pub capacity_level: Option<String>,
+    pub status: String,
+}
+
+impl Peripheral {
+    // reads one power_supply, None unless it is a peripheral battery that is
+    // still there
+    pub fn read(dir: &Path) -> Option<Peripheral> {
+        let name = dir.file_name()?.to_string_lossy().to_string();
+        let is_peripheral =
+            name.starts_with("hid-") || read_string_attr(dir, "scope").as_deref() == Some("Device");
+        let is_battery = match read_string_attr(dir, "type") {
+            Some(t) => t == "Battery",
+            None => true,
+        };
+        if !is_peripheral || !is_battery {
+            return None;
+        }
+        let capacity = read_u64_attr(dir, "capacity");
+        let capacity_level = read_string_attr(dir, "capacity_level");
+        // gone between listing the directory and reading it
+        if capacity.is_none() && capacity_level.is_none() {
+            return None;
+        }
+        Some(Peripheral {
+            name,
+            model: read_string_attr(dir, "model_name"),
+            serial: read_string_attr(dir, "serial_number"),
+            capacity,
+            capacity_level,
+            status: read_string_attr(dir, "status").unwrap_or_else(|| "Unknown".to_string()),
+        })
+    }
+
+    // the model when the driver has one, the power_supply name otherwise
+    pub fn label(&self) -> &str {
+        self.model.as_deref().unwrap_or(&self.name)
+    }
+
+    fn id(&self) -> String {
+        match (&self.serial, &self.model) {
+            (Some(serial), _) => serial.clone(),
+            (None, Some(model)) => model.clone(),
+            (None, None) => self.name.clone(),
+        }
+    }
+
+    fn charging(&self) -> bool {
+        self.status == "Charging" || self.status == "Full"
+    }
+}
+
+// what goes out when a peripheral runs low
+#[derive(Clone, Debug)]
+pub struct PeripheralAlert {
+    pub device: String,
+    pub level: Level,
+    pub capacity: Option<u64>,
+    pub message: String,
+}
+
+// the [config.peripherals.<key>] entry whose key is part of the device's
+// name or model, ignoring case. The first match in key order wins
+pub fn settings(configuration: &Config, device: &Peripheral) -> PeripheralConfig {
+    let name = device.name.to_lowercase();
+    let model = device.model.as_deref().unwrap_or("").to_lowercase();
+    configuration
+        .peripherals
+        .iter()
+        .find(|(key, _)| {
+            let key = key.to_lowercase();
+            name.contains(&key) || (!model.is_empty() && model.contains(&key))
+        })
+        .map(|(_, s)| s.clone())
+        .unwrap_or_default()
+}
+
+// scans for peripherals every peripheral_check_interval and alerts once per
+// level, again only after peripheral_alert_interval unless it got worse
+pub struct PeripheralMonitor {
+    configuration: Config,
+    clock: Arc<dyn Clock>,
+    root: PathBuf,
+    // power_supply names seen in the last scan
+    present: BTreeSet<String>,
+    // the level and time of the last alert per device id, kept while the
+    // device is away so reconnecting does not alert again
+    alerted: HashMap<String, (Level, u64)>,
+}
+
+impl PeripheralMonitor {
+    pub fn new(configuration: &Config, clock: Arc<dyn Clock>) -> PeripheralMonitor {
+        PeripheralMonitor {
+            configuration: configuration.clone(),
+            clock,
+            root: PathBuf::from("/sys/class/power_supply"),
+            present: BTreeSet::new(),
+            alerted: HashMap::new(),
+        }
+    }
+
+    pub fn with_root(mut self, root: &Path) -> PeripheralMonitor {
+        self.root = root.to_path_buf();
+        self
+    }
+
+    // every peripheral currently there, the main battery excluded
+    pub fn devices(&self) -> Vec<Peripheral> {
+        let battery = battery_dir(&self.configuration.path_to_capacity);
+        let mut dirs: Vec<PathBuf> = match fs::read_dir(&self.root) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return vec![],
+        };
+        dirs.sort();
+        dirs.iter()
+            .filter(|d| **d != battery)
+            .filter_map(|d| Peripheral::read(d))
+            .collect()
+    }
+
+    fn level(device: &Peripheral, settings: &PeripheralConfig, configuration: &Config) -> Level {
+        if device.charging() {
+            return Level::Normal;
+        }
+        let low = settings.low.unwrap_or(configuration.peripheral_low);
+        let critical = settings
+            .critical
+            .unwrap_or(configuration.peripheral_critical);
+        match (device.capacity, device.capacity_level.as_deref()) {
+            (Some(c), _) if c < critical => Level::Critical,
+            (Some(c), _) if c < low => Level::Low,
+            (Some(_), _) => Level::Normal,
+            (None, Some("Critical")) => Level::Critical,
+            (None, Some("Low")) => Level::Low,
+            (None, _) => Level::Normal,
+        }
+    }
+
+    fn message(device: &Peripheral, level: Level, settings: &PeripheralConfig) -> String {
+        match (settings.message.is_empty(), device.capacity) {
+            (false, capacity) => settings
+                .message
+                .replace("{name}", device.label())
+                .replace("{level}", level.as_str())
+                .replace(
+                    "{capacity}",
+                    &capacity.map_or("?".to_string(), |c| c.to_string()),
+                ),
+            (true, Some(c)) => format!(
+                "{} battery is {}: {}% left.",
+                device.label(),
+                level.as_str(),
+                c
+            ),
+            (true, None) => format!("{} battery is {}.", device.label(), level.as_str()),
+        }
+    }
+
+    pub fn poll(&mut self) -> (Vec<Event>, Duration) {
+        let interval = Duration::from_secs(self.configuration.peripheral_check_interval.max(1));
+        let now = self.clock.now();
+        let devices = self.devices();
+
+        let names: BTreeSet<String> = devices.iter().map(|d| d.name.clone()).collect();
+        for gone in self.present.difference(&names) {
+            println!("peripheral: {} went away", gone);
+        }
+        for device in devices.iter().filter(|d| !self.present.contains(&d.name)) {
+            println!("peripheral: watching {} ({})", device.label(), device.name);
+        }
+        self.present = names;
+
+        // identical devices without a serial number would share the model as
+        // their id, those fall back on the power_supply name
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        for device in &devices {
+            *ids.entry(device.id()).or_default() += 1;
+        }
+        let mut events = Vec::new();
+        for device in devices {
+            let settings = settings(&self.configuration, &device);
+            if settings.ignore {
+                continue;
+            }
+            let id = match ids.get(&device.id()) {
+                Some(n) if *n > 1 => device.name.clone(),
+                _ => device.id(),
+            };
+            let level = PeripheralMonitor::level(&device, &settings, &self.configuration);
+            if level == Level::Normal {
+                // a charge starts the next discharge afresh, a reading that
+                // wobbles back above the threshold does not
+                if device.charging() {
+                    self.alerted.remove(&id);
+                }
+                continue;
+            }
+            let due = match self.alerted.get(&id) {
+                None => true,
+                Some((Level::Low, _)) if level == Level::Critical => true,
+                Some((_, at)) => {
+                    now.saturating_sub(*at) >= self.configuration.peripheral_alert_interval
+                }
+            };
+            if !due {
+                continue;
+            }
+            self.alerted.insert(id, (level, now));
+            events.push(Event::PeripheralLow(PeripheralAlert {
+                device: device.label().to_string(),
+                level,
+                capacity: device.capacity,
+                message: PeripheralMonitor::message(&device, level, &settings),
+            }));
+        }
+        (events, interval)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+// seconds the do-not-disturb answer is reused, one event asks several times
+const DND_TTL: u64 = 10;
+
+// "mon-fri 22:00-07:00", a range ending before it starts runs past midnight
+// and belongs to the day it starts on
+#[derive(Clone, Debug)]
+struct Rule {
+    days: [bool; 7],
+    start: u32,
+    end: u32,
+}
+
+impl Rule {
+    fn parse(entry: &str) -> Option<Rule> {
+        let (days, times) = match entry.trim().split_once(' ') {
+            Some((d, t)) => (d.trim(), t.trim()),
+            None => ("*", entry.trim()),
+        };
+        let (start, end) = times.split_once('-')?;
+        Some(Rule {
+            days: parse_days(days)?,
+            start: parse_clock(start)?,
+            end: parse_clock(end)?,
+        })
+    }
+
+    fn matches(&self, weekday: usize, minute: u32) -> bool {
+        if self.start <= self.end {
+            return self.days[weekday] && minute >= self.start && minute < self.end;
+        }
+        let yesterday = (weekday + 6) % 7;
+        (self.days[weekday] && minute >= self.start) || (self.days[yesterday] && minute < self.end)
+    }
+}
+
+fn parse_day(name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    DAYS.iter().position(|d| name.starts_with(d))
+}
+
+// "*", "daily", "sat,sun", "mon-fri" or a mix like "mon-wed,sat"
+fn parse_days(value: &str) -> Option<[bool; 7]> {
+    if value == "*" || value == "daily" {
+        return Some([true; 7]);
+    }
+    let mut days = [false; 7];
+    for part in value.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let mut day = parse_day(from)?;
+                let to = parse_day(to)?;
+                days[day] = true;
+                while day != to {
+                    day = (day + 1) % 7;
+                    days[day] = true;
+                }
+            }
+            None => days[parse_day(part)?] = true,
+        }
+    }
+    Some(days)
+}
+
+// "HH:MM" to minutes since midnight, "24:00" is the end of the day
+fn parse_clock(value: &str) -> Option<u32> {
+    let (h, m) = value.split_once(':')?;
+    let h: u32 = h.parse().ok()?;
+    let m: u32 = m.parse().ok()?;
+    if h > 24 || m > 59 || (h == 24 && m != 0) {
+        return None;
+    }
+    Some(h * 60 + m)
+}
+
+// (weekday with sunday as 0, minutes since midnight) of a unix time in
+// local time
+fn local_time(now: u64) -> (usize, u32) {
+    let now = now as libc::time_t;
+    // SAFETY: localtime_r only writes into the tm we hand it
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        (
+            tm.tm_wday as usize % 7,
+            (tm.tm_hour * 60 + tm.tm_min) as u32,
+        )
+    }
+}
+
+// asks the notification server whether it is in do-not-disturb mode, first
+// through the Inhibited property of the notification spec, then dunst
+fn notification_server_inhibited() -> bool {
+    let inhibited = process::Command::new("busctl")
+        .args([
+            "--user",
+            "get-property",
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            "org.freedesktop.Notifications",
+            "Inhibited",
+        ])
+        .output();
+    if let Ok(out) = inhibited {
+        if out.status.success() {
+            return String::from_utf8_lossy(&out.stdout).trim() == "b true";
+        }
+    }
+    match process::Command::new("dunstctl").arg("is-paused").output() {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout).trim() == "true",
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
+pub struct QuietHours {
+    clock: Arc<dyn Clock>,
+    rules: Vec<Rule>,
+    mute_sounds: bool,
+    lower_urgency: bool,
+    allow_levels: Vec<String>,
+    follow_dnd: bool,
+    // asks whether the notification server is in do-not-disturb mode
+    dnd_probe: Arc<dyn Fn() -> bool + Send + Sync>,
+    // (when it was asked, the answer) of the last do-not-disturb query,
+    // shared between clones
+    dnd: Arc<Mutex<Option<(u64, bool)>>>,
+}
+
+impl QuietHours {
+    pub fn new(configuration: &Config) -> QuietHours {
+        QuietHours::with_clock(configuration, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(configuration: &Config, clock: Arc<dyn Clock>) -> QuietHours {
+        let mut rules = Vec::new();
+        for entry in &configuration.quiet_hours {
+            match Rule::parse(entry) {
+                Some(rule) => rules.push(rule),
+                None => println!("quiet_hours: ignoring invalid entry '{}'", entry),
+            }
+        }
+        QuietHours {
+            clock,
+            rules,
+            mute_sounds: configuration.quiet_mute_sounds,
+            lower_urgency: configuration.quiet_lower_urgency,
+            allow_levels: configuration.quiet_allow_levels.clone(),
+            follow_dnd: configuration.quiet_follow_dnd,
+            dnd_probe: Arc::new(notification_server_inhibited),
+            dnd: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // asks `probe` instead of busctl and dunstctl for do-not-disturb
+    pub fn with_dnd_probe<F>(mut self, probe: F) -> QuietHours
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.dnd_probe = Arc::new(probe);
+        self
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        let (weekday, minute) = local_time(self.clock.now());
+        if self.rules.iter().any(|r| r.matches(weekday, minute)) {
+            return true;
+        }
+        self.follow_dnd && self.dnd_inhibited()
+    }
+
+    fn dnd_inhibited(&self) -> bool {
+        let now = self.clock.now();
+        let mut dnd = self.dnd.lock().unwrap_or_else(|e| e.into_inner());
+        match *dnd {
+            Some((at, inhibited)) if now >= at && now - at < DND_TTL => inhibited,
+            _ => {
+                let inhibited = (self.dnd_probe)();
+                *dnd = Some((now, inhibited));
+                inhibited
+            }
+        }
+    }
+
+    // `level` is the alert level or event: "startup", "low", "critical",
+    // "near_dead", "plug", "drain", "health"
+    fn silenced(&self, level: &str) -> bool {
+        !self.allow_levels.iter().any(|l| l == level) && self.is_quiet()
+    }
+
+    pub fn allows_sound(&self, level: &str) -> bool {
+        !(self.mute_sounds && self.silenced(level))
+    }
+
+    pub fn urgency(&self, level: &str) -> &'static str {
+        match self.lower_urgency && self.silenced(level) {
+            true => "low",
+            false => "critical",
+        }
+    }
+}
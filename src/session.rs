@@ -0,0 +1,118 @@
+use std::env;
+
+// what the current login looks like, read once from the environment
+pub struct SessionInfo {
+    // XDG_CURRENT_DESKTOP split on ':', e.g. "ubuntu:GNOME"
+    pub desktops: Vec<String>,
+    pub session_type: String,
+    pub desktop_session: String,
+    pub graphical: bool,
+}
+
+impl SessionInfo {
+    pub fn from_env() -> SessionInfo {
+        let var = |name: &str| env::var(name).unwrap_or_default();
+        let graphical = !var("WAYLAND_DISPLAY").is_empty()
+            || !var("DISPLAY").is_empty()
+            || matches!(&var("XDG_SESSION_TYPE")[..], "x11" | "wayland");
+        SessionInfo {
+            desktops: var("XDG_CURRENT_DESKTOP")
+                .split(':')
+                .filter(|d| !d.is_empty())
+                .map(|d| d.to_string())
+                .collect(),
+            session_type: var("XDG_SESSION_TYPE"),
+            desktop_session: var("DESKTOP_SESSION"),
+            graphical,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "XDG_CURRENT_DESKTOP='{}' XDG_SESSION_TYPE='{}' DESKTOP_SESSION='{}' {}",
+            self.desktops.join(":"),
+            self.session_type,
+            self.desktop_session,
+            match self.graphical {
+                true => "graphical",
+                false => "tty",
+            }
+        )
+    }
+
+    // "any", "tty", "graphical", "type:<glob>", "session:<glob>" or
+    // "[desktop:]<glob>" matched against every XDG_CURRENT_DESKTOP entry
+    pub fn matches(&self, rule: &str) -> bool {
+        match rule {
+            "any" => return true,
+            "tty" => return !self.graphical,
+            "graphical" => return self.graphical,
+            _ => {}
+        }
+        if let Some(pattern) = rule.strip_prefix("type:") {
+            return glob_match(pattern, &self.session_type);
+        }
+        if let Some(pattern) = rule.strip_prefix("session:") {
+            return glob_match(pattern, &self.desktop_session);
+        }
+        let pattern = rule.strip_prefix("desktop:").unwrap_or(rule);
+        self.desktops.iter().any(|d| glob_match(pattern, d))
+    }
+}
+
+// case-insensitive glob with '*' and '?'
+pub fn glob_match(pattern: &str, value: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let v: Vec<char> = value.to_lowercase().chars().collect();
+    let (mut pi, mut vi) = (0, 0);
+    // position of the last '*' and the value index it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while vi < v.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == v[vi]) {
+            pi += 1;
+            vi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, vi));
+            pi += 1;
+        } else if let Some((sp, sv)) = star {
+            pi = sp + 1;
+            vi = sv + 1;
+            star = Some((sp, sv + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// None when the session is targeted, otherwise the reason why not
+pub fn check_session(target: &[String], exclude: &[String]) -> Option<String> {
+    let info = SessionInfo::from_env();
+    if let Some(rule) = exclude.iter().find(|r| info.matches(r)) {
+        return Some(format!(
+            "session excluded by exclude_session rule '{}' ({})",
+            rule,
+            info.describe()
+        ));
+    }
+    if target.iter().any(|r| info.matches(r)) {
+        return None;
+    }
+    Some(format!(
+        "no target_session rule {:?} matches this session ({})",
+        target,
+        info.describe()
+    ))
+}
+
+// the machine's name as webhooks and MQTT report it
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    let ok = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ok != 0 {
+        return "unknown".to_string();
+    }
+    let end = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).to_string()
+}
@@ -0,0 +1,57 @@
+use crate::control;
+use crate::state;
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+// one flag every loop waits on, set by a signal, a panic or the end of main
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            state: Arc::new((Mutex::new(false), Condvar::new())),
+        }
+    }
+
+    pub fn trigger(&self) {
+        let (lock, wakeup) = &*self.state;
+        // a thread that panicked while holding the lock cannot stop the others
+        let mut triggered = lock.lock().unwrap_or_else(|e| e.into_inner());
+        *triggered = true;
+        wakeup.notify_all();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.state.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // sleeps for `duration` unless shutdown is triggered first, returns false
+    // when the caller should stop
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let (lock, wakeup) = &*self.state;
+        let triggered = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (triggered, _) = wakeup
+            .wait_timeout_while(triggered, duration, |t| !*t)
+            .unwrap_or_else(|e| e.into_inner());
+        !*triggered
+    }
+
+    pub fn wait(&self) {
+        let (lock, wakeup) = &*self.state;
+        let triggered = lock.lock().unwrap_or_else(|e| e.into_inner());
+        drop(wakeup.wait_while(triggered, |t| !*t));
+    }
+}
+
+// removes the control socket and the state file, safe to call more than
+// once. The lock file stays: unlinking it while we still run would let a new
+// instance lock a fresh file next to ours, and the flock goes away with the
+// process anyway
+pub fn cleanup() {
+    let _ = fs::remove_file(control::socket_path());
+    let _ = fs::remove_file(state::state_path());
+}
@@ -0,0 +1,319 @@
+use crate::alerts::{Alerter, Effects};
+use crate::battery::{BatterySource, FakeBattery};
+use crate::clock::{Clock, VirtualClock};
+use crate::config::{Config, SoundConfig};
+use crate::estimate::format_duration;
+use crate::monitor::*;
+use std::cell::Cell;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+// timestamps at least this large are absolute unix times, not offsets
+const ABSOLUTE_TIME: u64 = 1_000_000_000;
+// where a trace of offsets starts without --start, fixed so that the same
+// trace always replays the same way: 2024-01-01 12:00 UTC
+const DEFAULT_START: u64 = 1_704_110_400;
+
+struct TracePoint {
+    offset: u64,
+    capacity: u64,
+    status: String,
+}
+
+// "90", "90s", "15m", "2h" or "1d" to seconds
+fn parse_offset(value: &str) -> Option<u64> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (num, unit) = value.split_at(split);
+    let num: u64 = num.parse().ok()?;
+    match unit {
+        "" | "s" => Some(num),
+        "m" => num.checked_mul(60),
+        "h" => num.checked_mul(3600),
+        "d" => num.checked_mul(86400),
+        _ => None,
+    }
+}
+
+fn parse_status(value: &str) -> String {
+    match value {
+        "C" => "Charging",
+        "D" => "Discharging",
+        "F" => "Full",
+        "N" => "Not charging",
+        _ => value,
+    }
+    .to_string()
+}
+
+// one "<time> <capacity> <status>" per line, separated by spaces or commas.
+// The time is an offset from the start of the trace or a unix timestamp, so
+// `history --format csv` output works as is. Lines that do not start with a
+// number (headers, # comments) are skipped. Returns the points and the unix
+// time of the first one when the trace had absolute times
+fn parse_trace(contents: &str) -> Result<(Vec<TracePoint>, Option<u64>), String> {
+    let mut points = Vec::new();
+    let mut start: Option<u64> = None;
+    for (n, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .collect();
+        match fields.first() {
+            Some(f) if f.starts_with(|c: char| c.is_ascii_digit()) => {}
+            _ => continue,
+        }
+        if fields.len() < 3 {
+            return Err(format!(
+                "line {}: expected <time> <capacity> <status>",
+                n + 1
+            ));
+        }
+        let mut offset = match parse_offset(fields[0]) {
+            Some(o) => o,
+            None => return Err(format!("line {}: invalid time '{}'", n + 1, fields[0])),
+        };
+        if offset >= ABSOLUTE_TIME {
+            let first = *start.get_or_insert(offset);
+            offset = offset.saturating_sub(first);
+        }
+        let capacity: u64 = match fields[1].parse() {
+            Ok(c) => c,
+            Err(_) => return Err(format!("line {}: invalid capacity '{}'", n + 1, fields[1])),
+        };
+        if points
+            .last()
+            .is_some_and(|p: &TracePoint| offset < p.offset)
+        {
+            return Err(format!("line {}: time goes backwards", n + 1));
+        }
+        points.push(TracePoint {
+            offset,
+            capacity,
+            status: parse_status(fields[2]),
+        });
+    }
+    if points.is_empty() {
+        return Err("the trace has no samples".to_string());
+    }
+    Ok((points, start))
+}
+
+// prints what the daemon would have done, stamped with the simulated time
+struct PrintEffects {
+    clock: VirtualClock,
+    start: u64,
+    alarm_until: Cell<u64>,
+}
+
+// "[+01:05:30] what", the simulated time since the start of the trace
+fn print_at(clock: &VirtualClock, start: u64, what: &str) {
+    let elapsed = clock.now().saturating_sub(start);
+    println!(
+        "[+{:02}:{:02}:{:02}] {}",
+        elapsed / 3600,
+        (elapsed % 3600) / 60,
+        elapsed % 60,
+        what
+    );
+}
+
+impl PrintEffects {
+    fn print(&self, what: &str) {
+        print_at(&self.clock, self.start, what);
+    }
+}
+
+impl Effects for PrintEffects {
+    fn log(&self, _line: &str) {}
+
+    // every poll would print one, the level changes say the same
+    fn status(&self, _line: &str) {}
+
+    fn notify(&self, message: String, _progress: u64, urgency: &str) {
+        self.print(&format!(
+            "notify ({}) {}",
+            urgency,
+            message.replace('\n', " / ")
+        ));
+    }
+
+    fn notify_action(
+        &self,
+        message: String,
+        label: &str,
+        urgency: &str,
+        _on_click: Box<dyn FnOnce() + Send>,
+    ) {
+        self.print(&format!(
+            "notify ({}) {} [{}]",
+            urgency,
+            message.replace('\n', " / "),
+            label
+        ));
+    }
+
+    fn play(&self, event: &str, sound: &SoundConfig) {
+        if sound.path == "none" || sound.repeat == 0 {
+            return;
+        }
+        self.print(&format!(
+            "sound {} : {} x{} at {}%",
+            event, sound.path, sound.repeat, sound.volume
+        ));
+    }
+
+    fn run_hook(&self, command: &str, env: &[(String, String)]) {
+        if command.trim().is_empty() {
+            return;
+        }
+        let vars: Vec<String> = env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        self.print(&format!("hook {} ({})", command, vars.join(" ")));
+    }
+
+    // the simulated alarm runs for its whole maximum duration, plugging in
+    // re-arms it all the same
+    fn start_alarm(&self, configuration: &Config) {
+        self.alarm_until
+            .set(self.clock.now() + configuration.alarm_max_duration);
+        self.print(&format!(
+            "alarm started ({}s at most) [Acknowledge]",
+            configuration.alarm_max_duration
+        ));
+    }
+
+    fn alarm_running(&self) -> bool {
+        self.clock.now() < self.alarm_until.get()
+    }
+}
+
+// the trace is fed to the monitor through a fake battery and a virtual clock
+fn replay(configuration: &Config, points: &[TracePoint], start: u64) -> Result<(), String> {
+    let mut configuration = configuration.clone();
+    // keep the simulation away from the real history, health record, running
+    // processes and do-not-disturb state
+    configuration.history_enable = false;
+    configuration.health_check_enable = false;
+    configuration.drain_detect_enable = false;
+    configuration.quiet_follow_dnd = false;
+
+    let clock = VirtualClock::new(start);
+    let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+    let battery = FakeBattery::new(points[0].capacity, &points[0].status);
+    let source: Arc<dyn BatterySource> = Arc::new(battery.clone());
+    let mut level = LevelMonitor::new(&configuration, Arc::clone(&shared), Arc::clone(&source));
+    let mut plug = PlugMonitor::new(&configuration, Arc::clone(&shared), source);
+    let effects = PrintEffects {
+        clock: clock.clone(),
+        start,
+        alarm_until: Cell::new(0),
+    };
+    let mut alerter = Alerter::new(&configuration, Box::new(effects), shared);
+
+    let end = points[points.len() - 1].offset;
+    // when each loop polls next, both get a last poll at the end of the trace
+    let mut level_at: Option<u64> = Some(0);
+    let mut plug_at: Option<u64> = match configuration.enable_plug_in_check {
+        true => Some(0),
+        false => None,
+    };
+    let next = |now: u64, sleep: Duration| match now >= end {
+        true => None,
+        false => Some((now + sleep.as_secs().max(1)).min(end)),
+    };
+    let mut polls: u64 = 0;
+    let mut current = 0;
+    loop {
+        let (now, is_plug) = match (level_at, plug_at) {
+            (Some(l), Some(p)) if p < l => (p, true),
+            (Some(l), _) => (l, false),
+            (None, Some(p)) => (p, true),
+            (None, None) => break,
+        };
+        clock.set(start + now);
+        while current + 1 < points.len() && points[current + 1].offset <= now {
+            current += 1;
+        }
+        battery.set(points[current].capacity, &points[current].status);
+        let events = match is_plug {
+            true => {
+                let (events, sleep) = plug.poll();
+                plug_at = next(now, sleep);
+                events
+            }
+            false => {
+                let (events, sleep) = level.poll();
+                level_at = next(now, sleep);
+                polls += 1;
+                events
+            }
+        };
+        for event in events {
+            match &event {
+                Event::LevelCrossed { from, to, reading } => print_at(
+                    &clock,
+                    start,
+                    &format!(
+                        "level {} -> {} at {}% {}",
+                        from.as_str(),
+                        to.as_str(),
+                        reading.capacity(),
+                        reading.status()
+                    ),
+                ),
+                Event::ReadError(e) => return Err(e.clone()),
+                _ => {}
+            }
+            alerter.handle(event);
+        }
+    }
+    println!(
+        "simulated {} of battery life in {} polls",
+        format_duration(end),
+        polls
+    );
+    Ok(())
+}
+
+// `--simulate <trace> [--start <unix time>]`
+pub fn run_subcommand(configuration: &Config, args: &[String]) -> i32 {
+    let (path, start_flag) = match args {
+        [path] => (path, None),
+        [path, flag, value] if flag == "--start" => match value.parse::<u64>() {
+            Ok(s) => (path, Some(s)),
+            Err(_) => {
+                println!("simulate: invalid start time '{}'", value);
+                return 1;
+            }
+        },
+        _ => {
+            println!("simulate: usage: --simulate <trace> [--start <unix time>]");
+            return 1;
+        }
+    };
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("simulate: could not read {} : {}", path, e);
+            return 1;
+        }
+    };
+    let (points, start) = match parse_trace(&contents) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("simulate: {} : {}", path, e);
+            return 1;
+        }
+    };
+    // absolute times in the trace win over --start
+    let start = start.or(start_flag).unwrap_or(DEFAULT_START);
+    match replay(configuration, &points, start) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("simulate: {}", e);
+            1
+        }
+    }
+}
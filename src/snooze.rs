@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// unix time the reminders are silenced until, 0 when they are not and
+// u64::MAX while paused. Clones share the same state
+#[derive(Clone, Default)]
+pub struct Snooze {
+    until: Arc<AtomicU64>,
+}
+
+impl Snooze {
+    pub fn new() -> Snooze {
+        Snooze::default()
+    }
+
+    // a huge number of minutes snoozes for as long as it can without
+    // turning into a pause
+    pub fn snooze(&self, now: u64, minutes: u64) {
+        let until = now.saturating_add(minutes.saturating_mul(60));
+        self.until.store(until.min(u64::MAX - 1), Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.until.store(u64::MAX, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.until.store(0, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.until.load(Ordering::Relaxed) == u64::MAX
+    }
+
+    // the end of a running snooze, None when paused or not snoozed
+    pub fn snoozed_until(&self, now: u64) -> Option<u64> {
+        match self.until.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            until if until > now => Some(until),
+            _ => None,
+        }
+    }
+
+    pub fn is_active(&self, now: u64) -> bool {
+        self.is_paused() || self.snoozed_until(now).is_some()
+    }
+
+    // "off", "paused" or "snoozed"
+    pub fn describe(&self, now: u64) -> &'static str {
+        match (self.is_paused(), self.snoozed_until(now)) {
+            (true, _) => "paused",
+            (false, Some(_)) => "snoozed",
+            (false, None) => "off",
+        }
+    }
+}
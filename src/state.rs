@@ -0,0 +1,159 @@
+use crate::alarm::Alarm;
+use crate::config::Config;
+use crate::history::unix_now;
+use crate::monitor::*;
+use crate::paths;
+use crate::quiet::QuietHours;
+use crate::snooze::Snooze;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+// bumped whenever a field changes meaning or goes away, new fields are fine
+pub const STATE_VERSION: u64 = 1;
+
+pub fn state_path() -> PathBuf {
+    paths::runtime_dir().join("batt_reminder.json")
+}
+
+// the daemon's view of the battery for other tools, rewritten whenever an
+// event from either monitor loop changes it
+pub struct StateFile {
+    path: PathBuf,
+    alarm: Alarm,
+    snooze: Snooze,
+    quiet: QuietHours,
+    reading: Option<Reading>,
+    level: Level,
+    // (kind, unix time, details) of the last event other than a poll
+    last_event: Option<(&'static str, u64, Value)>,
+    // what was last written, without the update time
+    written: Option<Value>,
+}
+
+impl StateFile {
+    pub fn new(configuration: &Config, alarm: &Alarm, snooze: &Snooze) -> StateFile {
+        StateFile {
+            path: state_path(),
+            alarm: alarm.clone(),
+            snooze: snooze.clone(),
+            quiet: QuietHours::new(configuration),
+            reading: None,
+            level: Level::Normal,
+            last_event: None,
+            written: None,
+        }
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        let now = unix_now();
+        match event {
+            Event::Polled { reading, level, .. } => {
+                self.reading = Some(reading.clone());
+                self.level = *level;
+            }
+            Event::LevelCrossed { from, to, reading } => {
+                self.reading = Some(reading.clone());
+                self.level = *to;
+                self.last_event = Some((
+                    "level_crossed",
+                    now,
+                    json!({"from": from.as_str(), "to": to.as_str()}),
+                ));
+            }
+            Event::PluggedIn(reading) => {
+                self.reading = Some(reading.clone());
+                self.last_event = Some(("plugged_in", now, Value::Null));
+            }
+            Event::PluggedOut(reading) => {
+                self.reading = Some(reading.clone());
+                self.last_event = Some(("plugged_out", now, Value::Null));
+            }
+            Event::ReadError(e) => {
+                self.last_event = Some(("read_error", now, json!({"error": e})));
+            }
+            Event::DrainSpike(report) => {
+                self.last_event = Some((
+                    "drain_spike",
+                    now,
+                    json!({"rate": report.rate, "baseline": report.baseline}),
+                ));
+            }
+            Event::HealthDropped(level) => {
+                self.last_event = Some(("health_dropped", now, json!({"below": level})));
+            }
+            Event::PeripheralLow(alert) => {
+                self.last_event = Some((
+                    "peripheral_low",
+                    now,
+                    json!({
+                        "device": alert.device,
+                        "level": alert.level.as_str(),
+                        "capacity": alert.capacity,
+                    }),
+                ));
+            }
+        }
+        let state = self.to_json(now);
+        if self.written.as_ref() == Some(&state) {
+            return;
+        }
+        let mut file = state.clone();
+        file["updated"] = json!(now);
+        match self.write(&file) {
+            Ok(()) => self.written = Some(state),
+            Err(e) => println!("state: could not write {} : {}", self.path.display(), e),
+        }
+    }
+
+    fn to_json(&self, now: u64) -> Value {
+        let (capacity, status, time_remaining) = match &self.reading {
+            Some(r) => (
+                json!(r.capacity()),
+                json!(r.status()),
+                match &r.estimate {
+                    Some(e) => json!({
+                        "seconds": e.seconds,
+                        "kind": match e.to_full {
+                            true => "to_full",
+                            false => "to_empty",
+                        },
+                        "confidence": e.confidence.as_str(),
+                    }),
+                    None => Value::Null,
+                },
+            ),
+            None => (Value::Null, Value::Null, Value::Null),
+        };
+        let last_event = match &self.last_event {
+            Some((kind, at, details)) => json!({"kind": kind, "at": at, "details": details}),
+            None => Value::Null,
+        };
+        json!({
+            "version": STATE_VERSION,
+            "pid": std::process::id(),
+            "capacity": capacity,
+            "status": status,
+            "level": self.level.as_str(),
+            "time_remaining": time_remaining,
+            "snooze": {
+                "state": self.snooze.describe(now),
+                "until": self.snooze.snoozed_until(now),
+                "alarm_running": self.alarm.is_running(),
+                "alarm_acknowledged": self.alarm.is_acknowledged(),
+                "quiet_hours": self.quiet.is_quiet(),
+            },
+            "last_event": last_event,
+        })
+    }
+
+    // readers never see a half written file
+    fn write(&self, state: &Value) -> std::io::Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(format!("{:#}\n", state).as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
@@ -0,0 +1,153 @@
+use std::env;
+use std::fs;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+const UNIT_NAME: &str = "rust-batt-reminder.service";
+
+// sends a state like "READY=1" to the service manager, does nothing when not
+// started by systemd (no $NOTIFY_SOCKET)
+pub fn notify(state: &str) {
+    let socket = match env::var("NOTIFY_SOCKET") {
+        Ok(val) if !val.is_empty() => val,
+        _ => return,
+    };
+    let sock = match UnixDatagram::unbound() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    // a leading '@' means an abstract socket
+    let result = match socket.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())
+            .and_then(|addr| sock.send_to_addr(state.as_bytes(), &addr)),
+        None => sock.send_to(state.as_bytes(), &socket),
+    };
+    if let Err(e) = result {
+        println!("systemd: failed to notify {} : {}", socket, e);
+    }
+}
+
+// half of WatchdogSec= when the watchdog is enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != process::id() {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec / 2))
+}
+
+// sleeps through `sleep` while keeping the watchdog fed, so only a stuck
+// read or hook in the monitor loop (not a long sleep) lets the watchdog fire.
+// `sleep` returns false once shutdown is triggered, and so does this
+pub fn watchdog_sleep(duration: Duration, sleep: &dyn Fn(Duration) -> bool) -> bool {
+    let interval = match watchdog_interval() {
+        Some(i) => i,
+        None => return sleep(duration),
+    };
+    let mut left = duration;
+    loop {
+        notify("WATCHDOG=1");
+        if left.is_zero() {
+            return true;
+        }
+        let step = interval.min(left);
+        if !sleep(step) {
+            return false;
+        }
+        left -= step;
+    }
+}
+
+// one quoted word of a unit file command line, with the specifier `%` and
+// the variable `$` doubled, see systemd.service(5) and systemd.unit(5)
+fn quote_exec(word: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        match c {
+            '\\' | '"' => quoted.push('\\'),
+            '%' | '$' => quoted.push(c),
+            _ => {}
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn unit_file(exe: &str) -> String {
+    format!(
+        "[Unit]
+Description=Battery level reminder
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart={}
+Restart=on-failure
+WatchdogSec=60
+
+[Install]
+WantedBy=graphical-session.target
+",
+        quote_exec(exe)
+    )
+}
+
+fn unit_dir() -> PathBuf {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(val) if !val.is_empty() => PathBuf::from(val),
+        _ => PathBuf::from(env::var("HOME").unwrap_or_default()).join(".config"),
+    };
+    base.join("systemd/user")
+}
+
+fn systemctl(args: &[&str]) -> bool {
+    match process::Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+    {
+        Ok(status) => status.success(),
+        Err(e) => {
+            println!("failed to run systemctl : {}", e);
+            false
+        }
+    }
+}
+
+// `install-service [--no-enable]`, writes a user unit for this binary and
+// enables it
+pub fn run_install_subcommand(args: &[String]) -> i32 {
+    let exe = match env::current_exe() {
+        Ok(p) => p.display().to_string(),
+        Err(e) => {
+            println!("could not find the path of this binary : {}", e);
+            return 1;
+        }
+    };
+    let dir = unit_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        println!("could not create {} : {}", dir.display(), e);
+        return 1;
+    }
+    let path = dir.join(UNIT_NAME);
+    if let Err(e) = fs::write(&path, unit_file(&exe)) {
+        println!("could not write {} : {}", path.display(), e);
+        return 1;
+    }
+    println!("wrote {}", path.display());
+    if args.iter().any(|a| a == "--no-enable") {
+        return 0;
+    }
+    if !systemctl(&["daemon-reload"]) || !systemctl(&["enable", "--now", UNIT_NAME]) {
+        return 1;
+    }
+    println!("enabled {}", UNIT_NAME);
+    0
+}
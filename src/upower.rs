@@ -0,0 +1,236 @@
+use crate::battery::*;
+use crate::config::Config;
+use crate::estimate::{Confidence, Estimate};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use zbus::blocking::{connection, proxy, Connection, Proxy};
+use zbus::proxy::CacheProperties;
+
+const UPOWER: &str = "org.freedesktop.UPower";
+const DEVICE_INTERFACE: &str = "org.freedesktop.UPower.Device";
+const DISPLAY_DEVICE: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+// object path of upower_device: the display device when empty, a full path
+// or a name like battery_BAT0
+pub fn device_path(device: &str) -> String {
+    match device {
+        "" => DISPLAY_DEVICE.to_string(),
+        d if d.starts_with('/') => d.to_string(),
+        d => format!("/org/freedesktop/UPower/devices/{}", d),
+    }
+}
+
+// what UPower last said about the device
+#[derive(Clone, Debug)]
+struct Properties {
+    percentage: f64,
+    state: u32,
+    time_to_empty: i64,
+    time_to_full: i64,
+    native_path: String,
+}
+
+impl Properties {
+    // the sysfs status for UPower's State enum
+    fn status(&self) -> &'static str {
+        match self.state {
+            1 => "Charging",
+            2 | 3 => "Discharging",
+            4 => "Full",
+            5 | 6 => "Not charging",
+            _ => "Unknown",
+        }
+    }
+}
+
+// a UPower device on the system bus. The properties are read once and then
+// again on every PropertiesChanged, the monitor only reads the copy kept
+// here. While UPower is not on the bus the sysfs files are read instead
+pub struct UPowerBattery {
+    properties: Arc<Mutex<Option<Properties>>>,
+    listeners: Arc<Mutex<Vec<Sender<()>>>>,
+    fallback: SysfsBattery,
+}
+
+impl UPowerBattery {
+    pub fn system(configuration: &Config) -> Result<UPowerBattery, String> {
+        let connection = Connection::system().map_err(|e| format!("system bus : {}", e))?;
+        UPowerBattery::with_connection(configuration, connection)
+    }
+
+    // UPower on the bus at `address`, e.g. a private one with a mock service
+    pub fn with_address(configuration: &Config, address: &str) -> Result<UPowerBattery, String> {
+        let connection = connection::Builder::address(address)
+            .and_then(|b| b.build())
+            .map_err(|e| format!("{} : {}", address, e))?;
+        UPowerBattery::with_connection(configuration, connection)
+    }
+
+    fn with_connection(
+        configuration: &Config,
+        connection: Connection,
+    ) -> Result<UPowerBattery, String> {
+        let path = device_path(&configuration.upower_device);
+        let device = proxy::Builder::<Proxy>::new(&connection)
+            .destination(UPOWER)
+            .and_then(|b| b.path(path.clone()))
+            .and_then(|b| b.interface(DEVICE_INTERFACE))
+            .map(|b| b.cache_properties(CacheProperties::No))
+            .and_then(|b| b.build())
+            .map_err(|e| format!("{} : {}", path, e))?;
+        let properties_proxy = Proxy::new(
+            &connection,
+            UPOWER,
+            path.clone(),
+            "org.freedesktop.DBus.Properties",
+        )
+        .map_err(|e| format!("{} : {}", path, e))?;
+        // subscribed before the first read so no change is missed
+        let changes = properties_proxy
+            .receive_signal("PropertiesChanged")
+            .map_err(|e| format!("PropertiesChanged : {}", e))?;
+        let owners = device
+            .receive_owner_changed()
+            .map_err(|e| format!("{} : {}", UPOWER, e))?;
+
+        let battery = UPowerBattery {
+            properties: Arc::new(Mutex::new(None)),
+            listeners: Arc::new(Mutex::new(Vec::new())),
+            fallback: SysfsBattery::new(
+                &configuration.path_to_capacity,
+                &configuration.path_to_status,
+            ),
+        };
+        let watcher = Watcher {
+            device,
+            path,
+            properties: Arc::clone(&battery.properties),
+            listeners: Arc::clone(&battery.listeners),
+        };
+        watcher.refresh();
+        let on_owner = watcher.clone();
+        thread::spawn(move || {
+            // UPower restarting or going away, the properties are re-read
+            // from the new owner or dropped
+            for _ in owners {
+                on_owner.refresh();
+            }
+        });
+        thread::spawn(move || {
+            for _ in changes {
+                watcher.refresh();
+            }
+        });
+        Ok(battery)
+    }
+
+    fn properties(&self) -> Option<Properties> {
+        self.properties
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// keeps the properties of one device up to date
+#[derive(Clone)]
+struct Watcher {
+    device: Proxy<'static>,
+    path: String,
+    properties: Arc<Mutex<Option<Properties>>>,
+    listeners: Arc<Mutex<Vec<Sender<()>>>>,
+}
+
+impl Watcher {
+    fn read(&self) -> zbus::Result<Option<Properties>> {
+        // the display device of a machine without a battery
+        if !self.device.get_property::<bool>("IsPresent")? {
+            return Ok(None);
+        }
+        Ok(Some(Properties {
+            percentage: self.device.get_property("Percentage")?,
+            state: self.device.get_property("State")?,
+            time_to_empty: self.device.get_property("TimeToEmpty")?,
+            time_to_full: self.device.get_property("TimeToFull")?,
+            native_path: self.device.get_property("NativePath")?,
+        }))
+    }
+
+    fn refresh(&self) {
+        let read = self.read();
+        {
+            let mut properties = self.properties.lock().unwrap_or_else(|e| e.into_inner());
+            match (&*properties, &read) {
+                (None, Ok(Some(_))) => println!("upower: reading {}", self.path),
+                (Some(_), Ok(None)) => {
+                    println!("upower: {} has no battery, reading sysfs", self.path)
+                }
+                (Some(_), Err(e)) => println!("upower: lost {} ({}), reading sysfs", self.path, e),
+                _ => {}
+            }
+            *properties = read.unwrap_or(None);
+        }
+        let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+        listeners.retain(|l| l.send(()).is_ok());
+    }
+}
+
+impl BatterySource for UPowerBattery {
+    fn capacity(&self) -> Result<u64, String> {
+        match self.properties() {
+            Some(p) => Ok(p.percentage.round() as u64),
+            None => self.fallback.capacity(),
+        }
+    }
+
+    fn status(&self) -> Result<String, String> {
+        match self.properties() {
+            Some(p) => Ok(p.status().to_string()),
+            None => self.fallback.status(),
+        }
+    }
+
+    // the sysfs directory behind the device, the display device has none
+    fn dir(&self) -> Option<PathBuf> {
+        match self.properties() {
+            Some(p) if !p.native_path.is_empty() => {
+                let dir = Path::new("/sys/class/power_supply").join(&p.native_path);
+                match dir.is_dir() {
+                    true => Some(dir),
+                    false => None,
+                }
+            }
+            Some(_) => None,
+            None => self.fallback.dir(),
+        }
+    }
+
+    // UPower's TimeToEmpty / TimeToFull, 0 while it is still working it out
+    fn estimate(&self, status: &str) -> Option<Estimate> {
+        let p = self.properties()?;
+        let (seconds, to_full) = match status {
+            "Discharging" => (p.time_to_empty, false),
+            "Charging" => (p.time_to_full, true),
+            _ => return None,
+        };
+        match seconds > 0 {
+            true => Some(Estimate {
+                seconds: seconds as u64,
+                to_full,
+                confidence: Confidence::High,
+            }),
+            false => None,
+        }
+    }
+
+    fn changes(&self) -> Option<Receiver<()>> {
+        let (sender, receiver) = channel();
+        self.listeners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(sender);
+        Some(receiver)
+    }
+}
@@ -0,0 +1,393 @@
+use crate::config::Config;
+use crate::history::unix_now;
+use crate::monitor::*;
+use crate::paths;
+use crate::session::hostname;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// the backoff doubles after each failed attempt up to this many seconds
+const MAX_BACKOFF: u64 = 900;
+
+pub fn queue_path() -> PathBuf {
+    paths::state_dir().join("webhook_queue.json")
+}
+
+// one payload for one target, kept on disk until it was delivered
+#[derive(Serialize, Deserialize, Clone)]
+struct Delivery {
+    id: String,
+    url: String,
+    body: String,
+    attempts: u64,
+    next_try: u64,
+}
+
+// the pending deliveries, oldest first, saved after every change
+struct Queue {
+    path: PathBuf,
+    deliveries: Vec<Delivery>,
+    max: usize,
+}
+
+impl Queue {
+    fn load(path: PathBuf, max: usize) -> Queue {
+        let deliveries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Queue {
+            path,
+            deliveries,
+            max,
+        }
+    }
+
+    fn push(&mut self, delivery: Delivery) {
+        self.deliveries.push(delivery);
+        while self.deliveries.len() > self.max {
+            let dropped = self.deliveries.remove(0);
+            println!(
+                "webhook: queue full, dropping event {} for {}",
+                dropped.id, dropped.url
+            );
+        }
+        self.save();
+    }
+
+    // the oldest delivery of every target is the only one that may go out,
+    // so each target gets its events in order
+    fn heads(&self) -> Vec<Delivery> {
+        let mut heads: Vec<Delivery> = Vec::new();
+        for d in &self.deliveries {
+            if !heads.iter().any(|h| h.url == d.url) {
+                heads.push(d.clone());
+            }
+        }
+        heads
+    }
+
+    fn remove(&mut self, id: &str) {
+        self.deliveries.retain(|d| d.id != id);
+        self.save();
+    }
+
+    fn update(&mut self, delivery: Delivery) {
+        if let Some(d) = self.deliveries.iter_mut().find(|d| d.id == delivery.id) {
+            *d = delivery;
+        }
+        self.save();
+    }
+
+    // written to a temp file and renamed, a crash never loses the whole queue
+    fn save(&self) {
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp_path = self.path.with_extension("json.tmp");
+            let mut tmp = fs::File::create(&tmp_path)?;
+            tmp.write_all(serde_json::to_string(&self.deliveries)?.as_bytes())?;
+            tmp.sync_all()?;
+            fs::rename(&tmp_path, &self.path)
+        };
+        if let Err(e) = write() {
+            println!("webhook: could not save {} : {}", self.path.display(), e);
+        }
+    }
+}
+
+// "sha256=<hex>" HMAC of the body, sent as X-Batt-Reminder-Signature
+pub fn sign(secret: &str, body: &str) -> String {
+    let mac = hmac_sha256::HMAC::mac(body.as_bytes(), secret.as_bytes());
+    let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+fn reading_fields(payload: &mut Value, reading: &Reading) {
+    payload["capacity"] = json!(reading.capacity());
+    payload["status"] = json!(reading.status());
+    if let Some(e) = &reading.estimate {
+        payload["time_remaining"] = json!({
+            "seconds": e.seconds,
+            "kind": match e.to_full {
+                true => "to_full",
+                false => "to_empty",
+            },
+            "confidence": e.confidence.as_str(),
+        });
+    }
+}
+
+// POSTs a JSON payload to every webhook_urls target for the low, critical
+// and near dead levels, plug in/out and read failures. Deliveries are queued
+// on disk and retried with a doubling backoff while the target is offline
+pub struct Webhooks {
+    configuration: Config,
+    queue: Arc<Mutex<Queue>>,
+    wake: Sender<()>,
+    host: String,
+    sequence: u64,
+    // a failing battery read is reported once until it reads again
+    read_failing: bool,
+}
+
+impl Webhooks {
+    // None without any webhook_urls
+    pub fn start(configuration: &Config) -> Option<Webhooks> {
+        match configuration.webhook_urls.is_empty() {
+            true => None,
+            false => Some(Webhooks::with_queue(configuration, queue_path())),
+        }
+    }
+
+    // `path` is where the queue is kept across restarts
+    pub fn with_queue(configuration: &Config, path: PathBuf) -> Webhooks {
+        Webhooks::with_curl(configuration, path, Path::new("curl"))
+    }
+
+    // posts through the `curl` executable instead of the one on PATH
+    pub fn with_curl(configuration: &Config, path: PathBuf, curl: &Path) -> Webhooks {
+        let queue = Arc::new(Mutex::new(Queue::load(
+            path,
+            configuration.webhook_queue_size.max(1) as usize,
+        )));
+        let (wake, woken) = channel();
+        let worker_queue = Arc::clone(&queue);
+        let worker_configuration = configuration.clone();
+        let curl = curl.to_path_buf();
+        thread::spawn(move || loop {
+            let wait = flush(&worker_configuration, &curl, &worker_queue);
+            // a new event or the next retry, whichever comes first
+            match woken.recv_timeout(wait.unwrap_or(Duration::from_secs(3600))) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+        Webhooks {
+            configuration: configuration.clone(),
+            queue,
+            wake,
+            host: hostname(),
+            sequence: 0,
+            read_failing: false,
+        }
+    }
+
+    fn payload(&mut self, event: &Event) -> Option<Value> {
+        let mut payload = json!({});
+        match event {
+            Event::LevelCrossed { from, to, reading } if *to != Level::Normal => {
+                payload["event"] = json!(to.as_str());
+                payload["level"] = json!(to.as_str());
+                payload["from"] = json!(from.as_str());
+                reading_fields(&mut payload, reading);
+            }
+            Event::PluggedIn(reading) => {
+                payload["event"] = json!("plug_in");
+                reading_fields(&mut payload, reading);
+            }
+            Event::PluggedOut(reading) => {
+                payload["event"] = json!("plug_out");
+                reading_fields(&mut payload, reading);
+            }
+            Event::PeripheralLow(alert) => {
+                payload["event"] = json!("peripheral_low");
+                payload["level"] = json!(alert.level.as_str());
+                payload["device"] = json!(alert.device);
+                payload["capacity"] = json!(alert.capacity);
+            }
+            Event::ReadError(e) if !self.read_failing => {
+                self.read_failing = true;
+                payload["event"] = json!("read_error");
+                payload["error"] = json!(e);
+            }
+            Event::Polled { .. } => {
+                self.read_failing = false;
+                return None;
+            }
+            _ => return None,
+        }
+        let now = unix_now();
+        self.sequence += 1;
+        payload["id"] = json!(format!("{}-{}-{}", now, process::id(), self.sequence));
+        payload["timestamp"] = json!(now);
+        payload["host"] = json!(self.host);
+        Some(payload)
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        let payload = match self.payload(event) {
+            Some(p) => p,
+            None => return,
+        };
+        let id = payload["id"].as_str().unwrap_or("").to_string();
+        let body = payload.to_string();
+        {
+            let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+            for url in &self.configuration.webhook_urls {
+                queue.push(Delivery {
+                    id: format!("{}@{}", id, url),
+                    url: url.clone(),
+                    body: body.clone(),
+                    attempts: 0,
+                    next_try: 0,
+                });
+            }
+        }
+        let _ = self.wake.send(());
+    }
+}
+
+// sends every delivery that is due, returns how long until the next retry
+fn flush(configuration: &Config, curl: &Path, queue: &Mutex<Queue>) -> Option<Duration> {
+    let lock = || queue.lock().unwrap_or_else(|e| e.into_inner());
+    // targets that failed this round wait for their next try
+    let mut failed: Vec<String> = Vec::new();
+    loop {
+        let now = unix_now();
+        let heads = lock().heads();
+        let due = heads
+            .into_iter()
+            .find(|d| d.next_try <= now && !failed.contains(&d.url));
+        let mut delivery = match due {
+            Some(d) => d,
+            // the queue stays unlocked while curl runs
+            None => break,
+        };
+        match post(configuration, curl, &delivery.url, &delivery.body) {
+            Ok(()) => lock().remove(&delivery.id),
+            Err(e) => {
+                delivery.attempts += 1;
+                failed.push(delivery.url.clone());
+                if configuration.webhook_retries > 0
+                    && delivery.attempts > configuration.webhook_retries
+                {
+                    println!(
+                        "webhook: giving up on {} after {} attempts : {}",
+                        delivery.url, delivery.attempts, e
+                    );
+                    lock().remove(&delivery.id);
+                    continue;
+                }
+                let backoff = configuration
+                    .webhook_backoff
+                    .max(1)
+                    .saturating_mul(1 << (delivery.attempts - 1).min(20))
+                    .min(MAX_BACKOFF);
+                println!(
+                    "webhook: {} failed ({}), retrying in {}s",
+                    delivery.url, e, backoff
+                );
+                delivery.next_try = unix_now() + backoff;
+                lock().update(delivery);
+            }
+        }
+    }
+    let now = unix_now();
+    lock()
+        .heads()
+        .iter()
+        .map(|d| d.next_try.saturating_sub(now))
+        .min()
+        .map(|s| Duration::from_secs(s.max(1)))
+}
+
+// curl config quoting, see "--config" in curl(1)
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", escaped)
+}
+
+// the webhook_headers and the signature, which are credentials, go to curl
+// through a file only we can read instead of its command line, where every
+// local user could see them
+fn write_headers(configuration: &Config, body: &str) -> std::io::Result<PathBuf> {
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let path = paths::runtime_dir().join(format!(
+        "batt_reminder-webhook-{}-{}.curlrc",
+        process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    let mut config = String::new();
+    for header in &configuration.webhook_headers {
+        config.push_str(&format!("header = {}\n", quote(header)));
+    }
+    if !configuration.webhook_secret.is_empty() {
+        let signature = format!(
+            "X-Batt-Reminder-Signature: {}",
+            sign(&configuration.webhook_secret, body)
+        );
+        config.push_str(&format!("header = {}\n", quote(&signature)));
+    }
+    if let Err(e) = file.write_all(config.as_bytes()) {
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+// POSTs through curl, which takes care of https and proxies
+fn post(configuration: &Config, curl: &Path, url: &str, body: &str) -> Result<(), String> {
+    let headers = write_headers(configuration, body)
+        .map_err(|e| format!("could not write the curl config : {}", e))?;
+    let result = run_curl(configuration, curl, url, body, &headers);
+    let _ = fs::remove_file(&headers);
+    result
+}
+
+fn run_curl(
+    configuration: &Config,
+    curl: &Path,
+    url: &str,
+    body: &str,
+    headers: &Path,
+) -> Result<(), String> {
+    let mut child = process::Command::new(curl)
+        .args(["--silent", "--show-error", "--output", "/dev/null"])
+        .args(["--write-out", "%{http_code}"])
+        .args(["--max-time", &configuration.webhook_timeout.to_string()])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--header", "User-Agent: rust-batt-reminder"])
+        .arg("--config")
+        .arg(headers)
+        .args(["--data-binary", "@-", url])
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run curl : {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(body.as_bytes());
+    }
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("curl : {}", e))?;
+    let code = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    match code.starts_with('2') {
+        true => Ok(()),
+        false if code == "000" || code.is_empty() => {
+            Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+        }
+        false => Err(format!("HTTP {}", code)),
+    }
+}
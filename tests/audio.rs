@@ -0,0 +1,65 @@
+use rust_batt_reminder::audio::{AudioEngine, AudioOutput, NullOutput, Sound};
+use rust_batt_reminder::config::SoundConfig;
+use rust_batt_reminder::shutdown::Shutdown;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn sound(path: &str, repeat: u64, gap: u64) -> SoundConfig {
+    SoundConfig {
+        path: path.to_string(),
+        volume: 80,
+        repeat,
+        gap,
+        fade_in: 0,
+    }
+}
+
+#[test]
+fn plays_through_the_null_output() {
+    let shutdown = Shutdown::new();
+    let audio = AudioEngine::with_output(|| Box::new(NullOutput), &shutdown);
+    assert!(audio.play_and_wait(&sound("builtin:chime", 2, 10)));
+    assert!(audio.play_and_wait(&sound("builtin:beep-beep", 1, 0)));
+    // silence, an unknown sound and a missing file play nothing
+    assert!(!audio.play_and_wait(&sound("none", 1, 0)));
+    assert!(!audio.play_and_wait(&sound("builtin:chime", 0, 0)));
+    assert!(!audio.play_and_wait(&sound("builtin:nope", 1, 0)));
+    assert!(!audio.play_and_wait(&sound("/nonexistent/sound.wav", 1, 0)));
+}
+
+// a sound that lasts until shutdown, telling the test when it started
+struct EndlessOutput {
+    started: Sender<()>,
+}
+
+impl AudioOutput for EndlessOutput {
+    fn play(
+        &mut self,
+        _sound: &Sound,
+        _volume: f32,
+        _fade_in: Duration,
+        shutdown: &Shutdown,
+    ) -> Result<(), String> {
+        let _ = self.started.send(());
+        match shutdown.sleep(Duration::from_secs(600)) {
+            true => Ok(()),
+            false => Err("interrupted by shutdown".to_string()),
+        }
+    }
+}
+
+#[test]
+fn shutdown_cuts_a_sound_short() {
+    let shutdown = Shutdown::new();
+    let (started, playing) = channel();
+    let audio = AudioEngine::with_output(move || Box::new(EndlessOutput { started }), &shutdown);
+    let player = audio.clone();
+    let waiting = thread::spawn(move || player.play_and_wait(&sound("builtin:alarm", 5, 60_000)));
+    playing.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let triggered = Instant::now();
+    shutdown.trigger();
+    assert!(!waiting.join().unwrap());
+    assert!(triggered.elapsed() < Duration::from_secs(5));
+}
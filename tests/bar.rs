@@ -0,0 +1,96 @@
+mod common;
+
+use common::TempDir;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// a HOME whose config points at a sysfs battery of its own
+struct Home {
+    dir: TempDir,
+}
+
+impl Home {
+    fn new(name: &str) -> Home {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.join(".config")).unwrap();
+        fs::create_dir_all(dir.join("BAT0")).unwrap();
+        fs::write(
+            dir.join(".config/batt_reminder.toml"),
+            format!(
+                "[config]\npath_to_capacity = \"{}\"\npath_to_status = \"{}\"\n",
+                dir.join("BAT0/capacity").display(),
+                dir.join("BAT0/status").display()
+            ),
+        )
+        .unwrap();
+        let home = Home { dir };
+        home.set(50, "Discharging");
+        home
+    }
+
+    fn set(&self, capacity: u64, status: &str) {
+        fs::write(self.dir.join("BAT0/capacity"), format!("{}\n", capacity)).unwrap();
+        fs::write(self.dir.join("BAT0/status"), format!("{}\n", status)).unwrap();
+    }
+
+    fn bar(&self, format: &str) -> Child {
+        Command::new(env!("CARGO_BIN_EXE_rust-batt-reminder"))
+            .args(["bar", "--format", format, "--interval", "1"])
+            .env("HOME", self.dir.path())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+}
+
+// the exit status once `child` exits, panics after a few seconds
+fn exits(child: &mut Child) -> process::ExitStatus {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            let _ = child.kill();
+            panic!("the bar kept running");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn prints_on_change_and_exits_once_the_bar_goes_away() {
+    let home = Home::new("bar-plain");
+    let mut child = home.bar("plain");
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "50% Discharging");
+    home.set(49, "Discharging");
+    // two samples are enough for a rough estimate
+    let second = lines.next().unwrap().unwrap();
+    assert!(second.starts_with("49% ("), "{}", second);
+
+    // the bar restarting closes the pipe, the next update finds out
+    drop(lines);
+    home.set(48, "Discharging");
+    assert!(exits(&mut child).success());
+}
+
+#[test]
+fn i3bar_starts_with_the_protocol_header() {
+    let home = Home::new("bar-i3bar");
+    home.set(20, "Discharging");
+    let mut child = home.bar("i3bar");
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "{\"version\":1}");
+    assert_eq!(lines.next().unwrap().unwrap(), "[");
+    let block = lines.next().unwrap().unwrap();
+    assert!(block.contains("\"color\":\"#FF0000\""), "{}", block);
+    assert!(block.contains("\"short_text\":\"20%\""), "{}", block);
+    drop(lines);
+    let _ = child.kill();
+    let _ = child.wait();
+}
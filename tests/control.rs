@@ -0,0 +1,36 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::control;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn ask(path: &std::path::Path, command: &str) -> String {
+    let mut stream = UnixStream::connect(path).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+        .write_all(format!("{}\n", command).as_bytes())
+        .unwrap();
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).unwrap();
+    reply.trim().to_string()
+}
+
+#[test]
+fn a_silent_client_does_not_hold_up_the_next() {
+    let dir = TempDir::new("control-silent");
+    let path = dir.join("batt_reminder.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || control::serve(listener, |command| format!("got {}", command)));
+
+    // connects and never says anything
+    let _silent = UnixStream::connect(&path).unwrap();
+    let start = Instant::now();
+    assert_eq!(ask(&path, "pause"), "got pause");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,52 @@
+use rust_batt_reminder::estimate::{Confidence, Estimate, Estimator, TimeLevel, TimeThresholds};
+
+// a sample a minute, `capacity` going from `from` by `step` percent each
+fn push_minutes(estimator: &mut Estimator, start: u64, from: u64, step: i64, status: &str) {
+    for i in 0..10 {
+        let capacity = (from as i64 + step * i as i64) as u64;
+        estimator.push(start + 60 * i as u64, capacity, status);
+    }
+}
+
+#[test]
+fn a_status_change_starts_the_trend_over() {
+    let mut estimator = Estimator::new(3600);
+    push_minutes(&mut estimator, 1_700_000_000, 80, -1, "Discharging");
+    let discharging = estimator.slope().unwrap();
+    assert!((discharging + 1.0 / 60.0).abs() < 1e-9);
+
+    // straight from discharging to charging, no Full or Unknown in between
+    estimator.push(1_700_000_600, 69, "Charging");
+    assert!(estimator.slope().is_none());
+    push_minutes(&mut estimator, 1_700_000_660, 71, 2, "Charging");
+    let charging = estimator.slope().unwrap();
+    assert!((charging - 2.0 / 60.0).abs() < 1e-9);
+    let estimate = estimator.estimate(None, "Charging", 89).unwrap();
+    assert!(estimate.to_full);
+    // 11% at 2% a minute
+    assert!((329..=330).contains(&estimate.seconds));
+
+    // and back
+    estimator.push(1_700_001_300, 89, "Discharging");
+    assert!(estimator.slope().is_none());
+}
+
+#[test]
+fn full_and_unknown_keep_no_samples() {
+    let mut estimator = Estimator::new(3600);
+    push_minutes(&mut estimator, 1_700_000_000, 100, 0, "Full");
+    assert!(estimator.slope().is_none());
+    assert!(estimator.estimate(None, "Full", 100).is_none());
+}
+
+#[test]
+fn huge_time_thresholds_are_not_a_panic() {
+    let mut thresholds = TimeThresholds::new(u64::MAX, u64::MAX / 2, 1);
+    let estimate = Some(Estimate {
+        seconds: 3600,
+        to_full: false,
+        confidence: Confidence::High,
+    });
+    // an hour left is below any threshold that large
+    assert_eq!(thresholds.update(&estimate), TimeLevel::Critical);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::clock::VirtualClock;
+use rust_batt_reminder::config::{Config, Data};
+use rust_batt_reminder::health::HealthMonitor;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+// a battery directory and a state directory of their own
+struct FakeBattery {
+    root: TempDir,
+}
+
+impl FakeBattery {
+    fn new(name: &str) -> FakeBattery {
+        let root = TempDir::new(name);
+        fs::create_dir_all(root.join("BAT0")).unwrap();
+        fs::create_dir_all(root.join("state/batt_reminder")).unwrap();
+        fs::create_dir_all(root.join(".config")).unwrap();
+        FakeBattery { root }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.root.join("BAT0")
+    }
+
+    fn set_full(&self, full: u64, design: u64) {
+        fs::write(self.dir().join("energy_full"), format!("{}\n", full)).unwrap();
+        fs::write(
+            self.dir().join("energy_full_design"),
+            format!("{}\n", design),
+        )
+        .unwrap();
+    }
+
+    fn health_file(&self) -> PathBuf {
+        self.root.join("state/batt_reminder/health")
+    }
+}
+
+#[test]
+fn records_once_a_day_on_the_given_clock() {
+    let battery = FakeBattery::new("health-monitor");
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut monitor = HealthMonitor::new(&Config::default_config(), Arc::new(clock.clone()))
+        .with_file(&battery.health_file());
+    let dir = battery.dir();
+
+    battery.set_full(75_000_000, 100_000_000);
+    assert_eq!(monitor.check(Some(&dir)), Some(80));
+    // the same day is not recorded again
+    battery.set_full(55_000_000, 100_000_000);
+    clock.set(1_700_000_000 + 3600);
+    assert_eq!(monitor.check(Some(&dir)), None);
+    clock.set(1_700_000_000 + 86400);
+    // 70 and 60 are both crossed, only the lowest is alerted
+    assert_eq!(monitor.check(Some(&dir)), Some(60));
+
+    let records = fs::read_to_string(battery.health_file()).unwrap();
+    assert_eq!(
+        records,
+        "1700000000 75000000 100000000 -\n1700086400 55000000 100000000 -\n"
+    );
+    // nothing to read, nothing recorded
+    clock.set(1_700_000_000 + 2 * 86400);
+    assert_eq!(monitor.check(None), None);
+    assert_eq!(fs::read_to_string(battery.health_file()).unwrap(), records);
+}
+
+#[test]
+fn records_out_of_order_are_not_a_panic() {
+    let battery = FakeBattery::new("health-subcommand");
+    battery.set_full(80_000_000, 100_000_000);
+    // the clock was set back between the two records
+    fs::write(
+        battery.health_file(),
+        "1700086400 90000000 100000000 10\n1700000000 80000000 100000000 12\n",
+    )
+    .unwrap();
+    fs::write(
+        battery.root.join(".config/batt_reminder.toml"),
+        format!(
+            "[config]\npath_to_capacity = \"{}\"\n",
+            battery.dir().join("capacity").display()
+        ),
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-batt-reminder"))
+        .arg("health")
+        .env("HOME", battery.root.path())
+        .env("XDG_STATE_HOME", battery.root.join("state"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("\thealth : 80%"), "{}", stdout);
+    assert!(
+        stdout.contains("\trecorded : 90% -> 80% over 0 day(s)"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn an_empty_level_list_turns_the_alerts_off() {
+    let battery = FakeBattery::new("health-no-levels");
+    let data: Data = toml::from_str("[config]\nhealth_alert_levels = []\n").unwrap();
+    let configuration = Config::default_config().convert_data(data);
+    assert!(configuration.health_alert_levels.is_empty());
+
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut monitor =
+        HealthMonitor::new(&configuration, Arc::new(clock)).with_file(&battery.health_file());
+    battery.set_full(10_000_000, 100_000_000);
+    assert_eq!(monitor.check(Some(&battery.dir())), None);
+}
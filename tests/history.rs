@@ -0,0 +1,65 @@
+mod common;
+
+use common::TempDir;
+use std::fs;
+use std::process::{Command, Output};
+
+// a HOME of its own with `config` as batt_reminder.toml
+struct Home {
+    dir: TempDir,
+}
+
+impl Home {
+    fn new(name: &str, config: &str) -> Home {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.join(".config")).unwrap();
+        fs::write(dir.join(".config/batt_reminder.toml"), config).unwrap();
+        Home { dir }
+    }
+
+    fn history(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_rust-batt-reminder"))
+            .arg("history")
+            .args(args)
+            .env("HOME", self.dir.path())
+            .env_remove("XDG_STATE_HOME")
+            .output()
+            .unwrap()
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn bad_times_are_refused_not_a_panic() {
+    let home = Home::new("history-times", "[config]\n");
+    for time in ["5é", "é", "d", "30x", "99999999999999999d", "-5m"] {
+        let output = home.history(&["--from", time]);
+        assert_eq!(output.status.code(), Some(1), "{}", time);
+        assert_eq!(
+            stdout(&output).trim(),
+            format!("history: invalid time '{}'", time)
+        );
+    }
+    for time in ["now", "30s", "15m", "24h", "7d", "1700000000"] {
+        let output = home.history(&["--from", time]);
+        assert!(output.status.success(), "{}", time);
+        assert!(stdout(&output).starts_with("timestamp,capacity,status"));
+    }
+}
+
+#[test]
+fn an_out_of_range_retention_is_a_config_error() {
+    let home = Home::new(
+        "history-retention",
+        "[config]\nhistory_retention_days = 999999999999999999\n",
+    );
+    let output = home.history(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stdout(&output).trim(),
+        "history: history_retention_days = 999999999999999999 is too large"
+    );
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::battery::{BatterySource, FakeBattery};
+use rust_batt_reminder::clock::VirtualClock;
+use rust_batt_reminder::config::Config;
+use rust_batt_reminder::metrics::Metrics;
+use rust_batt_reminder::monitor::LevelMonitor;
+use std::fs;
+use std::sync::Arc;
+
+#[test]
+fn textfile_follows_the_readings_and_recovers() {
+    let root = TempDir::new("metrics-textfile");
+    // the directory for the textfile, not there yet
+    let textfile = root.join("textfile");
+    let path = textfile.join("batt_reminder.prom");
+    let mut configuration = Config::default_config();
+    configuration.health_check_enable = false;
+    configuration.drain_detect_enable = false;
+    configuration.metrics_mode = "textfile".to_string();
+    configuration.metrics_textfile = path.display().to_string();
+
+    let battery = FakeBattery::new(55, "Discharging");
+    let source: Arc<dyn BatterySource> = Arc::new(battery.clone());
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut level = LevelMonitor::new(&configuration, Arc::new(clock.clone()), Arc::clone(&source));
+    let metrics = Metrics::start(&configuration, &source).unwrap();
+
+    // the directory is not there yet, every poll fails without stopping
+    for _ in 0..3 {
+        for event in level.poll().0 {
+            metrics.update(&event);
+        }
+    }
+    assert!(!path.exists());
+
+    fs::create_dir_all(&textfile).unwrap();
+    battery.set(42, "Discharging");
+    clock.set(1_700_000_060);
+    for event in level.poll().0 {
+        metrics.update(&event);
+    }
+    let written = fs::read_to_string(&path).unwrap();
+    assert!(
+        written.contains("batt_reminder_capacity_percent 42\n"),
+        "{}",
+        written
+    );
+    assert!(written.contains("batt_reminder_status{status=\"Discharging\"} 1\n"));
+    assert!(written.contains("batt_reminder_last_poll_timestamp_seconds 1700000060\n"));
+    assert!(!textfile.join("batt_reminder.prom.tmp").exists());
+
+    metrics.stop();
+    assert!(!path.exists());
+}
+
+#[test]
+fn off_and_unknown_modes_start_nothing() {
+    let source: Arc<dyn BatterySource> = Arc::new(FakeBattery::new(55, "Discharging"));
+    let mut configuration = Config::default_config();
+    assert!(Metrics::start(&configuration, &source).is_none());
+    configuration.metrics_mode = "statsd".to_string();
+    assert!(Metrics::start(&configuration, &source).is_none());
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::alerts::{Alerter, Effects};
+use rust_batt_reminder::battery::{BatterySource, FakeBattery, SysfsBattery};
+use rust_batt_reminder::clock::{Clock, VirtualClock};
+use rust_batt_reminder::config::{Config, SoundConfig};
+use rust_batt_reminder::monitor::{LevelMonitor, PlugMonitor};
+use rust_batt_reminder::shutdown::Shutdown;
+use rust_batt_reminder::{Event, Level};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+// a power_supply directory with capacity and status files
+struct FakeSysfs {
+    root: TempDir,
+}
+
+impl FakeSysfs {
+    fn new(name: &str) -> FakeSysfs {
+        let root = TempDir::new(name);
+        fs::create_dir_all(root.join("BAT0")).unwrap();
+        FakeSysfs { root }
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.root.join("BAT0")
+    }
+
+    fn set(&self, capacity: u64, status: &str) {
+        fs::write(self.dir().join("capacity"), format!("{}\n", capacity)).unwrap();
+        fs::write(self.dir().join("status"), format!("{}\n", status)).unwrap();
+    }
+
+    fn source(&self) -> Arc<dyn BatterySource> {
+        Arc::new(SysfsBattery::new(
+            &self.dir().join("capacity").display().to_string(),
+            &self.dir().join("status").display().to_string(),
+        ))
+    }
+}
+
+// records what the alerter did instead of doing it
+#[derive(Clone, Default)]
+struct Recorder {
+    actions: Rc<RefCell<Vec<String>>>,
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<String> {
+        self.actions.borrow_mut().drain(..).collect()
+    }
+
+    fn push(&self, action: String) {
+        self.actions.borrow_mut().push(action);
+    }
+}
+
+impl Effects for Recorder {
+    fn log(&self, _line: &str) {}
+
+    fn status(&self, _line: &str) {}
+
+    fn notify(&self, _message: String, _progress: u64, urgency: &str) {
+        self.push(format!("notify {}", urgency));
+    }
+
+    fn notify_action(
+        &self,
+        _message: String,
+        label: &str,
+        _urgency: &str,
+        _on_click: Box<dyn FnOnce() + Send>,
+    ) {
+        self.push(format!("action {}", label));
+    }
+
+    // like the audio engine, "none" is silence
+    fn play(&self, event: &str, sound: &SoundConfig) {
+        if sound.path != "none" {
+            self.push(format!("sound {}", event));
+        }
+    }
+
+    fn run_hook(&self, command: &str, _env: &[(String, String)]) {
+        self.push(format!("hook {}", command));
+    }
+
+    fn start_alarm(&self, _configuration: &Config) {
+        self.push("alarm".to_string());
+    }
+
+    fn alarm_running(&self) -> bool {
+        false
+    }
+}
+
+fn test_config() -> Config {
+    let mut configuration = Config::default_config();
+    configuration.history_enable = false;
+    configuration.health_check_enable = false;
+    configuration.drain_detect_enable = false;
+    configuration.quiet_follow_dnd = false;
+    configuration.bat_low_command_to_exec = "low-hook".to_string();
+    configuration.bat_crit_command_to_exec = "crit-hook".to_string();
+    configuration.near_ded_command = "near-dead-hook".to_string();
+    configuration.plug_in_check_command_to_exec = "plug-in-hook".to_string();
+    configuration.plug_out_check_command_to_exec = "plug-out-hook".to_string();
+    configuration
+}
+
+fn crossings(events: &[Event]) -> Vec<(Level, Level)> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            Event::LevelCrossed { from, to, .. } => Some((*from, *to)),
+            _ => None,
+        })
+        .collect()
+}
+
+// polls once and hands every event to the alerter
+fn step(level: &mut LevelMonitor, alerter: &mut Alerter) -> Vec<Event> {
+    let (events, _) = level.poll();
+    for event in events.clone() {
+        alerter.handle(event);
+    }
+    events
+}
+
+#[test]
+fn levels_follow_the_capacity() {
+    let configuration = test_config();
+    let sysfs = FakeSysfs::new("levels");
+    let clock: Arc<dyn Clock> = Arc::new(VirtualClock::new(1_700_000_000));
+    let mut level = LevelMonitor::new(&configuration, clock, sysfs.source());
+
+    sysfs.set(60, "Discharging");
+    let (events, sleep) = level.poll();
+    assert!(crossings(&events).is_empty());
+    assert_eq!(
+        sleep,
+        Duration::from_secs(configuration.normal_sleep_time + 5)
+    );
+
+    sysfs.set(44, "Discharging");
+    let (events, sleep) = level.poll();
+    assert_eq!(crossings(&events), vec![(Level::Normal, Level::Low)]);
+    assert_eq!(
+        sleep,
+        Duration::from_secs(configuration.fast_sleep_time + 5)
+    );
+
+    sysfs.set(29, "Discharging");
+    let (events, sleep) = level.poll();
+    assert_eq!(crossings(&events), vec![(Level::Low, Level::Critical)]);
+    assert_eq!(
+        sleep,
+        Duration::from_secs(configuration.critical_sleep_time + 5)
+    );
+
+    // plugging in ends the episode whatever the capacity
+    sysfs.set(29, "Charging");
+    let (events, _) = level.poll();
+    assert_eq!(crossings(&events), vec![(Level::Critical, Level::Normal)]);
+}
+
+#[test]
+fn near_dead_only_below_both_thresholds() {
+    let mut configuration = test_config();
+    configuration.battery_low = 0;
+    configuration.battery_critical = 0;
+    let battery = FakeBattery::new(8, "Discharging");
+    let clock: Arc<dyn Clock> = Arc::new(VirtualClock::new(1_700_000_000));
+    let mut level = LevelMonitor::new(&configuration, clock.clone(), Arc::new(battery));
+    let recorder = Recorder::default();
+    let mut alerter = Alerter::new(&configuration, Box::new(recorder.clone()), clock);
+
+    let events = step(&mut level, &mut alerter);
+    assert_eq!(crossings(&events), vec![(Level::Normal, Level::NearDead)]);
+    // near dead warns and runs its command on every poll
+    step(&mut level, &mut alerter);
+    assert_eq!(
+        recorder.take(),
+        vec![
+            "notify critical",
+            "hook near-dead-hook",
+            "notify critical",
+            "hook near-dead-hook"
+        ]
+    );
+}
+
+#[test]
+fn hooks_run_once_until_rearmed() {
+    let configuration = test_config();
+    let sysfs = FakeSysfs::new("rearm");
+    let clock: Arc<dyn Clock> = Arc::new(VirtualClock::new(1_700_000_000));
+    let mut level = LevelMonitor::new(&configuration, clock.clone(), sysfs.source());
+    let recorder = Recorder::default();
+    let mut alerter = Alerter::new(&configuration, Box::new(recorder.clone()), clock);
+
+    sysfs.set(44, "Discharging");
+    step(&mut level, &mut alerter);
+    assert_eq!(recorder.take(), vec!["hook low-hook"]);
+    sysfs.set(43, "Discharging");
+    step(&mut level, &mut alerter);
+    assert!(recorder.take().is_empty());
+
+    // back above the low threshold re-arms the hooks
+    sysfs.set(60, "Discharging");
+    step(&mut level, &mut alerter);
+    assert!(recorder.take().is_empty());
+
+    sysfs.set(20, "Discharging");
+    step(&mut level, &mut alerter);
+    assert_eq!(
+        recorder.take(),
+        vec!["hook crit-hook", "notify critical", "sound critical"]
+    );
+    step(&mut level, &mut alerter);
+    assert_eq!(recorder.take(), vec!["notify critical", "sound critical"]);
+}
+
+#[test]
+fn plug_events() {
+    let configuration = test_config();
+    let battery = FakeBattery::new(50, "Discharging");
+    let clock: Arc<dyn Clock> = Arc::new(VirtualClock::new(1_700_000_000));
+    let mut plug = PlugMonitor::new(&configuration, clock, Arc::new(battery.clone()));
+    let interval = Duration::from_secs(configuration.plug_in_check_interval);
+
+    // the first poll only learns the current status
+    let (events, sleep) = plug.poll();
+    assert!(events.is_empty());
+    assert_eq!(sleep, interval);
+
+    battery.set(51, "Charging");
+    let (events, _) = plug.poll();
+    match &events[..] {
+        [Event::PluggedIn(reading)] => {
+            assert_eq!(reading.capacity(), 51);
+            assert_eq!(reading.status(), "Charging");
+        }
+        _ => panic!("expected PluggedIn, got {:?}", events),
+    }
+
+    let (events, sleep) = plug.poll();
+    assert!(events.is_empty());
+    assert_eq!(sleep, interval + Duration::from_secs(5));
+
+    battery.set(51, "Discharging");
+    let (events, _) = plug.poll();
+    assert!(matches!(&events[..], [Event::PluggedOut(_)]));
+}
+
+#[test]
+fn missing_battery_is_a_read_error() {
+    let configuration = test_config();
+    let sysfs = FakeSysfs::new("missing");
+    let clock: Arc<dyn Clock> = Arc::new(VirtualClock::new(1_700_000_000));
+    let mut level = LevelMonitor::new(&configuration, clock, sysfs.source());
+
+    let (events, sleep) = level.poll();
+    assert!(matches!(&events[..], [Event::ReadError(_)]));
+    assert_eq!(sleep, Duration::from_secs(5));
+}
+
+#[test]
+fn estimates_follow_the_virtual_clock() {
+    let configuration = test_config();
+    let battery = FakeBattery::new(80, "Discharging");
+    let clock = VirtualClock::new(1_700_000_000);
+    let shutdown = Shutdown::new();
+    let mut level = LevelMonitor::new(
+        &configuration,
+        Arc::new(clock.clone()),
+        Arc::new(battery.clone()),
+    );
+
+    // one percent per minute
+    let mut last = None;
+    for capacity in (70..=80).rev() {
+        battery.set(capacity, "Discharging");
+        let (events, _) = level.poll();
+        last = events.into_iter().find_map(|e| match e {
+            Event::Polled { reading, .. } => Some(reading),
+            _ => None,
+        });
+        assert!(clock.sleep(Duration::from_secs(60), &shutdown));
+    }
+    let reading = last.unwrap();
+    assert_eq!(reading.sample.timestamp, 1_700_000_000 + 10 * 60);
+    let estimate = reading.estimate.unwrap();
+    assert!(!estimate.to_full);
+    assert_eq!(estimate.seconds, 70 * 60);
+}
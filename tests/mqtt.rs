@@ -0,0 +1,395 @@
+use rust_batt_reminder::config::Config;
+use rust_batt_reminder::history::Sample;
+use rust_batt_reminder::mqtt::Mqtt;
+use rust_batt_reminder::snooze::Snooze;
+use rust_batt_reminder::{Event, Level, Reading};
+use serde_json::Value;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// what the client sent to the broker
+#[derive(Debug)]
+enum Packet {
+    Connect {
+        client_id: String,
+        will_topic: String,
+        keepalive: u16,
+    },
+    Subscribe(String),
+    Publish {
+        topic: String,
+        payload: String,
+        retain: bool,
+    },
+    Disconnect,
+    Other,
+}
+
+fn read_str(body: &[u8], at: &mut usize) -> String {
+    let len = u16::from_be_bytes([body[*at], body[*at + 1]]) as usize;
+    let s = String::from_utf8(body[*at + 2..*at + 2 + len].to_vec()).unwrap();
+    *at += 2 + len;
+    s
+}
+
+fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let mut first = [0u8; 1];
+    stream.read_exact(&mut first).ok()?;
+    let (mut len, mut shift) = (0usize, 0);
+    loop {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).ok()?;
+        len |= ((byte[0] & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).ok()?;
+    Some((first[0], body))
+}
+
+fn decode(first: u8, body: &[u8]) -> Packet {
+    let mut at = 0;
+    match first & 0xf0 {
+        0x10 => {
+            assert_eq!(read_str(body, &mut at), "MQTT");
+            assert_eq!(body[at], 4);
+            let keepalive = u16::from_be_bytes([body[at + 2], body[at + 3]]);
+            at += 4;
+            let client_id = read_str(body, &mut at);
+            let will_topic = read_str(body, &mut at);
+            Packet::Connect {
+                client_id,
+                will_topic,
+                keepalive,
+            }
+        }
+        0x80 => {
+            at = 2;
+            Packet::Subscribe(read_str(body, &mut at))
+        }
+        0x30 => {
+            let topic = read_str(body, &mut at);
+            if (first >> 1) & 3 > 0 {
+                at += 2;
+            }
+            Packet::Publish {
+                topic,
+                payload: String::from_utf8(body[at..].to_vec()).unwrap(),
+                retain: first & 1 == 1,
+            }
+        }
+        0xe0 => Packet::Disconnect,
+        _ => Packet::Other,
+    }
+}
+
+fn publish(topic: &str, payload: &str) -> Vec<u8> {
+    let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+    body.extend_from_slice(topic.as_bytes());
+    body.extend_from_slice(payload.as_bytes());
+    let mut out = vec![0x30, body.len() as u8];
+    out.extend(body);
+    out
+}
+
+// a local broker for one client: acknowledges CONNECT and SUBSCRIBE, hands
+// every packet to the test and sends the client whatever the test writes
+fn stand_in() -> (String, Receiver<Packet>, Receiver<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (packets, received) = channel();
+    let (streams, connected): (Sender<TcpStream>, _) = channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = streams.send(stream.try_clone().unwrap());
+        while let Some((first, body)) = read_packet(&mut stream) {
+            match first & 0xf0 {
+                0x10 => stream.write_all(&[0x20, 2, 0, 0]).unwrap(),
+                0x80 => stream.write_all(&[0x90, 3, body[0], body[1], 0]).unwrap(),
+                _ => {}
+            }
+            let _ = packets.send(decode(first, &body));
+        }
+    });
+    (address, received, connected)
+}
+
+fn mqtt_config(broker: &str) -> Config {
+    let mut configuration = Config::default_config();
+    configuration.mqtt_broker = broker.to_string();
+    configuration.mqtt_client_id = "test-client".to_string();
+    configuration.mqtt_topic_prefix = "test/laptop".to_string();
+    configuration
+}
+
+// the packets until one matches, panics after a few seconds
+fn wait_for<F>(packets: &Receiver<Packet>, matches: F) -> Packet
+where
+    F: Fn(&Packet) -> bool,
+{
+    loop {
+        let packet = packets
+            .recv_timeout(Duration::from_secs(5))
+            .expect("the client went quiet");
+        if matches(&packet) {
+            return packet;
+        }
+    }
+}
+
+fn published(packets: &Receiver<Packet>, wanted: &str) -> (String, bool) {
+    match wait_for(
+        packets,
+        |p| matches!(p, Packet::Publish { topic, .. } if topic == wanted),
+    ) {
+        Packet::Publish {
+            payload, retain, ..
+        } => (payload, retain),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn publishes_state_and_discovery() {
+    let (broker, packets, _) = stand_in();
+    let mqtt = Mqtt::start(&mqtt_config(&broker), &Snooze::new(), |_| String::new()).unwrap();
+
+    match wait_for(&packets, |p| matches!(p, Packet::Connect { .. })) {
+        Packet::Connect {
+            client_id,
+            will_topic,
+            keepalive,
+        } => {
+            assert_eq!(client_id, "test-client");
+            assert_eq!(will_topic, "test/laptop/available");
+            assert_eq!(keepalive, 60);
+        }
+        _ => unreachable!(),
+    }
+    assert!(matches!(
+        wait_for(&packets, |p| matches!(p, Packet::Subscribe(_))),
+        Packet::Subscribe(t) if t == "test/laptop/command"
+    ));
+    assert_eq!(
+        published(&packets, "test/laptop/available"),
+        ("online".to_string(), true)
+    );
+    let discovery = wait_for(&packets, |p| {
+        matches!(p, Packet::Publish { topic, .. }
+            if topic.starts_with("homeassistant/sensor/") && topic.ends_with("/capacity/config"))
+    });
+    match discovery {
+        Packet::Publish {
+            payload, retain, ..
+        } => {
+            let config: Value = serde_json::from_str(&payload).unwrap();
+            assert_eq!(config["state_topic"], "test/laptop/capacity");
+            assert_eq!(config["device_class"], "battery");
+            assert!(retain);
+        }
+        _ => unreachable!(),
+    }
+
+    mqtt.update(&Event::Polled {
+        reading: Reading {
+            sample: Sample::read(None, 1_700_000_000, 42, "Discharging"),
+            estimate: None,
+        },
+        level: Level::Low,
+        smoothed: None,
+    });
+    assert_eq!(
+        published(&packets, "test/laptop/capacity"),
+        ("42".to_string(), true)
+    );
+    assert_eq!(published(&packets, "test/laptop/status").0, "Discharging");
+    assert_eq!(published(&packets, "test/laptop/level").0, "low");
+    assert_eq!(published(&packets, "test/laptop/snooze").0, "off");
+
+    mqtt.stop();
+    assert_eq!(published(&packets, "test/laptop/available").0, "offline");
+    wait_for(&packets, |p| matches!(p, Packet::Disconnect));
+}
+
+#[test]
+fn runs_commands_from_the_command_topic() {
+    let (broker, packets, connected) = stand_in();
+    let snooze = Snooze::new();
+    let (commands, received) = channel();
+    let handler_snooze = snooze.clone();
+    let mut configuration = mqtt_config(&broker);
+    configuration.mqtt_discovery_prefix = "".to_string();
+    let _mqtt = Mqtt::start(&configuration, &snooze, move |command| {
+        commands.send(command.to_string()).unwrap();
+        handler_snooze.pause();
+        "reminders paused until resumed".to_string()
+    })
+    .unwrap();
+
+    let mut stream = connected.recv_timeout(Duration::from_secs(5)).unwrap();
+    wait_for(&packets, |p| matches!(p, Packet::Subscribe(_)));
+    stream
+        .write_all(&publish("test/laptop/command", "pause\n"))
+        .unwrap();
+    assert_eq!(
+        received.recv_timeout(Duration::from_secs(5)).unwrap(),
+        "pause"
+    );
+    assert_eq!(
+        published(&packets, "test/laptop/command/reply"),
+        ("reminders paused until resumed".to_string(), false)
+    );
+    assert_eq!(published(&packets, "test/laptop/snooze").0, "paused");
+    // only the discovery prefix is off, nothing went there
+    assert!(packets.try_iter().all(|p| !matches!(p,
+        Packet::Publish { topic, .. } if topic.starts_with("homeassistant"))));
+}
+
+// accepts one client and answers its CONNECT, the test does the rest
+fn raw_broker() -> (String, Receiver<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (streams, connected) = channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream).unwrap();
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        let _ = streams.send(stream);
+    });
+    (address, connected)
+}
+
+#[test]
+fn oversized_packets_drop_the_connection() {
+    let (broker, connected) = raw_broker();
+    let _mqtt = Mqtt::start(&mqtt_config(&broker), &Snooze::new(), |_| String::new()).unwrap();
+    let mut stream = connected.recv_timeout(Duration::from_secs(5)).unwrap();
+    // a PUBLISH claiming to be 256 MB long
+    stream.write_all(&[0x30, 0xff, 0xff, 0xff, 0x7f]).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // the client hangs up instead of waiting for the rest
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => break,
+            Err(e) => panic!("the client kept the connection : {}", e),
+        }
+    }
+}
+
+#[test]
+fn a_stalled_broker_does_not_hold_up_updates() {
+    let (broker, connected) = raw_broker();
+    let mqtt = Mqtt::start(&mqtt_config(&broker), &Snooze::new(), |_| String::new()).unwrap();
+    // connected, but nothing is ever read from the client again
+    let _stream = connected.recv_timeout(Duration::from_secs(5)).unwrap();
+    let start = std::time::Instant::now();
+    for capacity in 0..50_000 {
+        mqtt.update(&Event::Polled {
+            reading: Reading {
+                sample: Sample::read(None, 1_700_000_000, capacity, "Discharging"),
+                estimate: None,
+            },
+            level: Level::Normal,
+            smoothed: None,
+        });
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+// (topic, packet id, DUP flag) of a QoS 1 PUBLISH
+fn qos1_publish(first: u8, body: &[u8]) -> Option<(String, u16, bool)> {
+    if first & 0xf0 != 0x30 {
+        return None;
+    }
+    assert_eq!((first >> 1) & 3, 1, "not sent at QoS 1");
+    let mut at = 0;
+    let topic = read_str(body, &mut at);
+    Some((
+        topic,
+        u16::from_be_bytes([body[at], body[at + 1]]),
+        first & 0x08 != 0,
+    ))
+}
+
+#[test]
+fn qos_1_sends_unacknowledged_publishes_again() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut configuration = mqtt_config(&listener.local_addr().unwrap().to_string());
+    configuration.mqtt_qos = 1;
+    configuration.mqtt_discovery_prefix = "".to_string();
+    let mqtt = Mqtt::start(&configuration, &Snooze::new(), |_| String::new()).unwrap();
+
+    // the first broker acknowledges everything up to the capacity, then goes away
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut capacity_id = None;
+    while capacity_id.is_none() {
+        let (first, body) = read_packet(&mut stream).unwrap();
+        match first & 0xf0 {
+            0x10 => {
+                // the last will goes out at QoS 1 too
+                assert_eq!((body[7] >> 3) & 3, 1);
+                stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            }
+            0x80 => {
+                assert_eq!(body.last(), Some(&1));
+                stream.write_all(&[0x90, 3, body[0], body[1], 1]).unwrap();
+            }
+            _ => match qos1_publish(first, &body) {
+                Some((topic, id, _)) if topic == "test/laptop/capacity" => capacity_id = Some(id),
+                Some((topic, id, _)) => {
+                    stream.write_all(&[0x40, 2]).unwrap();
+                    stream.write_all(&id.to_be_bytes()).unwrap();
+                    if topic == "test/laptop/available" {
+                        mqtt.update(&Event::Polled {
+                            reading: Reading {
+                                sample: Sample::read(None, 1_700_000_000, 42, "Discharging"),
+                                estimate: None,
+                            },
+                            level: Level::Low,
+                            smoothed: None,
+                        });
+                    }
+                }
+                None => {}
+            },
+        }
+    }
+    drop(stream);
+
+    // the client reconnects and sends the capacity again before anything new
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut again = Vec::new();
+    loop {
+        let (first, body) = read_packet(&mut stream).unwrap();
+        match first & 0xf0 {
+            0x10 => stream.write_all(&[0x20, 2, 0, 0]).unwrap(),
+            _ => match qos1_publish(first, &body) {
+                Some((topic, _, false)) if topic == "test/laptop/available" => break,
+                Some((topic, id, true)) => again.push((topic, id)),
+                _ => {}
+            },
+        }
+    }
+    assert!(again.contains(&("test/laptop/capacity".to_string(), capacity_id.unwrap())));
+    assert!(again
+        .iter()
+        .all(|(topic, _)| topic != "test/laptop/available"));
+}
+
+#[test]
+fn only_qos_0_and_1_are_supported() {
+    let mut configuration = mqtt_config("127.0.0.1:1");
+    configuration.mqtt_qos = 2;
+    assert!(Mqtt::start(&configuration, &Snooze::new(), |_| String::new()).is_none());
+}
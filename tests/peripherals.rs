@@ -0,0 +1,224 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::clock::VirtualClock;
+use rust_batt_reminder::config::{Config, PeripheralConfig};
+use rust_batt_reminder::peripherals::{PeripheralAlert, PeripheralMonitor};
+use rust_batt_reminder::{Event, Level};
+use std::fs;
+use std::sync::Arc;
+
+// a /sys/class/power_supply of its own
+struct FakePowerSupply {
+    root: TempDir,
+}
+
+impl FakePowerSupply {
+    fn new(name: &str) -> FakePowerSupply {
+        FakePowerSupply {
+            root: TempDir::new(name),
+        }
+    }
+
+    // writes every (attribute, value) of one power_supply
+    fn set(&self, name: &str, attributes: &[(&str, &str)]) {
+        let dir = self.root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        fs::remove_dir_all(self.root.join(name)).unwrap();
+    }
+
+    fn monitor(&self, configuration: &Config, clock: &VirtualClock) -> PeripheralMonitor {
+        PeripheralMonitor::new(configuration, Arc::new(clock.clone())).with_root(self.root.path())
+    }
+}
+
+fn mouse(supply: &FakePowerSupply, capacity: u64, status: &str) {
+    supply.set(
+        "hid-00:11:22:33:44:55-battery",
+        &[
+            ("type", "Battery"),
+            ("model_name", "MX Master 3"),
+            ("capacity", &capacity.to_string()),
+            ("status", status),
+        ],
+    );
+}
+
+fn alerts(monitor: &mut PeripheralMonitor) -> Vec<PeripheralAlert> {
+    monitor
+        .poll()
+        .0
+        .into_iter()
+        .map(|e| match e {
+            Event::PeripheralLow(alert) => alert,
+            other => panic!("unexpected {:?}", other),
+        })
+        .collect()
+}
+
+// a game controller without a serial number
+fn controller(capacity: &str) -> [(&str, &str); 4] {
+    [
+        ("scope", "Device"),
+        ("model_name", "Wireless Controller"),
+        ("capacity", capacity),
+        ("status", "Discharging"),
+    ]
+}
+
+fn levels(alerts: &[PeripheralAlert]) -> Vec<(String, Level)> {
+    alerts.iter().map(|a| (a.device.clone(), a.level)).collect()
+}
+
+#[test]
+fn alerts_once_per_level_and_again_after_the_interval() {
+    let supply = FakePowerSupply::new("peripherals-rate");
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut monitor = supply.monitor(&Config::default_config(), &clock);
+    let mx = "MX Master 3".to_string();
+
+    mouse(&supply, 15, "Discharging");
+    let first = alerts(&mut monitor);
+    assert_eq!(levels(&first), vec![(mx.clone(), Level::Low)]);
+    assert_eq!(first[0].message, "MX Master 3 battery is low: 15% left.");
+    assert_eq!(first[0].capacity, Some(15));
+    assert!(alerts(&mut monitor).is_empty());
+
+    // a reading wobbling around the threshold is not a new alert
+    mouse(&supply, 22, "Discharging");
+    assert!(alerts(&mut monitor).is_empty());
+    mouse(&supply, 18, "Discharging");
+    assert!(alerts(&mut monitor).is_empty());
+
+    // getting worse alerts at once, staying there only once per interval
+    mouse(&supply, 5, "Discharging");
+    assert_eq!(
+        levels(&alerts(&mut monitor)),
+        vec![(mx.clone(), Level::Critical)]
+    );
+    clock.set(1_700_000_000 + 1800);
+    assert!(alerts(&mut monitor).is_empty());
+    clock.set(1_700_000_000 + 3600);
+    assert_eq!(
+        levels(&alerts(&mut monitor)),
+        vec![(mx.clone(), Level::Critical)]
+    );
+
+    // charging starts over
+    mouse(&supply, 40, "Charging");
+    assert!(alerts(&mut monitor).is_empty());
+    mouse(&supply, 15, "Discharging");
+    assert_eq!(levels(&alerts(&mut monitor)), vec![(mx, Level::Low)]);
+}
+
+#[test]
+fn thresholds_and_messages_per_device() {
+    let supply = FakePowerSupply::new("peripherals-settings");
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut configuration = Config::default_config();
+    configuration.peripherals.insert(
+        "mx master".to_string(),
+        PeripheralConfig {
+            low: Some(50),
+            message: "Charge the {name}, {capacity}% ({level})".to_string(),
+            ..Default::default()
+        },
+    );
+    configuration.peripherals.insert(
+        "K380".to_string(),
+        PeripheralConfig {
+            ignore: true,
+            ..Default::default()
+        },
+    );
+    // the main battery and a charger are not peripherals
+    supply.set("BAT0", &[("type", "Battery"), ("capacity", "3")]);
+    supply.set(
+        "ucsi-source-psy-0",
+        &[("type", "USB"), ("scope", "Device"), ("capacity", "0")],
+    );
+    configuration.path_to_capacity = supply.root.join("BAT0/capacity").display().to_string();
+    mouse(&supply, 45, "Discharging");
+    supply.set(
+        "hid-aa:bb:cc:dd:ee:ff-battery",
+        &[("model_name", "K380"), ("capacity", "1")],
+    );
+    // a headset that only reports a level, matched by its name
+    supply.set(
+        "headset_battery",
+        &[
+            ("scope", "Device"),
+            ("capacity_level", "Critical"),
+            ("status", "Discharging"),
+        ],
+    );
+    let mut monitor = supply.monitor(&configuration, &clock);
+
+    let alerts = alerts(&mut monitor);
+    assert_eq!(
+        levels(&alerts),
+        vec![
+            ("headset_battery".to_string(), Level::Critical),
+            ("MX Master 3".to_string(), Level::Low),
+        ]
+    );
+    assert_eq!(alerts[0].message, "headset_battery battery is critical.");
+    assert_eq!(alerts[0].capacity, None);
+    assert_eq!(alerts[1].message, "Charge the MX Master 3, 45% (low)");
+}
+
+#[test]
+fn devices_may_come_and_go() {
+    let supply = FakePowerSupply::new("peripherals-gone");
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut monitor = supply.monitor(&Config::default_config(), &clock);
+    let keyboard = [
+        ("scope", "Device"),
+        ("model_name", "G915"),
+        ("serial_number", "1234-abcd"),
+        ("capacity", "8"),
+        ("status", "Discharging"),
+    ];
+
+    supply.set("hidpp_battery_0", &keyboard);
+    assert_eq!(
+        levels(&alerts(&mut monitor)),
+        vec![("G915".to_string(), Level::Critical)]
+    );
+    supply.remove("hidpp_battery_0");
+    assert!(alerts(&mut monitor).is_empty());
+    // reconnecting renumbers it, the serial number keeps the rate limit
+    supply.set("hidpp_battery_1", &keyboard);
+    assert!(alerts(&mut monitor).is_empty());
+    supply.remove("hidpp_battery_1");
+    assert!(alerts(&mut monitor).is_empty());
+
+    // a missing power_supply directory is just no peripherals
+    drop(supply);
+    assert!(alerts(&mut monitor).is_empty());
+}
+
+#[test]
+fn identical_devices_without_a_serial_are_told_apart() {
+    let supply = FakePowerSupply::new("peripherals-twins");
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut monitor = supply.monitor(&Config::default_config(), &clock);
+    let pad = "Wireless Controller".to_string();
+
+    supply.set("ps-controller-battery-aa", &controller("15"));
+    supply.set("ps-controller-battery-bb", &controller("15"));
+    assert_eq!(
+        levels(&alerts(&mut monitor)),
+        vec![(pad.clone(), Level::Low), (pad.clone(), Level::Low)]
+    );
+    assert!(alerts(&mut monitor).is_empty());
+    // one running lower does not hide the other
+    supply.set("ps-controller-battery-bb", &controller("5"));
+    assert_eq!(levels(&alerts(&mut monitor)), vec![(pad, Level::Critical)]);
+}
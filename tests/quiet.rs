@@ -0,0 +1,48 @@
+use rust_batt_reminder::clock::VirtualClock;
+use rust_batt_reminder::config::Config;
+use rust_batt_reminder::quiet::QuietHours;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn do_not_disturb_is_asked_once_per_event() {
+    // what the notification server answers and how often it was asked
+    let inhibited = Arc::new(AtomicBool::new(true));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut configuration = Config::default_config();
+    configuration.quiet_follow_dnd = true;
+    let (answer, asked) = (Arc::clone(&inhibited), Arc::clone(&calls));
+    let quiet =
+        QuietHours::with_clock(&configuration, Arc::new(clock.clone())).with_dnd_probe(move || {
+            asked.fetch_add(1, Ordering::Relaxed);
+            answer.load(Ordering::Relaxed)
+        });
+
+    // one notification checks the sound and the urgency
+    assert!(!quiet.allows_sound("low"));
+    assert_eq!(quiet.urgency("low"), "low");
+    assert!(quiet.clone().is_quiet());
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    inhibited.store(false, Ordering::Relaxed);
+    clock.set(1_700_000_000 + 60);
+    assert!(quiet.allows_sound("low"));
+    assert_eq!(quiet.urgency("low"), "critical");
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn only_24_00_is_accepted_past_23_59() {
+    let clock = VirtualClock::new(1_700_000_000);
+    let mut configuration = Config::default_config();
+    configuration.quiet_follow_dnd = false;
+    configuration.quiet_hours = vec!["00:00-24:00".to_string()];
+    assert!(QuietHours::with_clock(&configuration, Arc::new(clock.clone())).is_quiet());
+    // the whole day whatever the time zone, unless the entry is refused
+    for entry in ["00:00-24:30", "00:00-24:01", "00:00-25:00"] {
+        configuration.quiet_hours = vec![entry.to_string()];
+        let quiet = QuietHours::with_clock(&configuration, Arc::new(clock.clone()));
+        assert!(!quiet.is_quiet(), "{}", entry);
+    }
+}
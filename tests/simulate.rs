@@ -0,0 +1,97 @@
+mod common;
+
+use common::TempDir;
+use std::fs;
+use std::process::Command;
+
+// a HOME of its own with `config` as batt_reminder.toml
+struct Home {
+    dir: TempDir,
+}
+
+impl Home {
+    fn new(name: &str, config: &str) -> Home {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.join(".config")).unwrap();
+        fs::write(dir.join(".config/batt_reminder.toml"), config).unwrap();
+        Home { dir }
+    }
+
+    // the output of `--simulate` on the fixture, run in UTC
+    fn simulate(&self, extra: &[&str]) -> Vec<String> {
+        let trace = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/discharge.trace"
+        );
+        let output = Command::new(env!("CARGO_BIN_EXE_rust-batt-reminder"))
+            .arg("--simulate")
+            .arg(trace)
+            .args(extra)
+            .env("HOME", self.dir.path())
+            .env("TZ", "UTC")
+            .env_remove("NOTIFY_SOCKET")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        assert!(output.status.success(), "{}", stdout);
+        stdout.lines().map(|l| l.to_string()).collect()
+    }
+}
+
+const CONFIG: &str = "[config]
+history_enable = false
+quiet_hours = [\"12:30-13:30\"]
+quiet_allow_levels = []
+";
+
+#[test]
+fn replays_the_fixture_the_same_way_every_time() {
+    let home = Home::new("simulate-fixture", CONFIG);
+    let lines = home.simulate(&[]);
+    for expected in [
+        "[+00:20:20] level normal -> low at 44% Discharging",
+        "[+00:40:00] level low -> critical at 29% Discharging",
+        "[+00:56:40] level critical -> normal at 26% Charging",
+        "simulated 1h 10m of battery life in 134 polls",
+    ] {
+        assert!(
+            lines.iter().any(|l| l == expected),
+            "no '{}' in {:#?}",
+            expected,
+            lines
+        );
+    }
+    // the trace starts at 12:00 UTC, the critical level comes in quiet hours
+    assert!(!lines.iter().any(|l| l.contains("sound critical")));
+    assert_eq!(home.simulate(&[]), lines);
+}
+
+#[test]
+fn start_moves_the_trace() {
+    let home = Home::new("simulate-start", CONFIG);
+    // 2024-01-01 08:00 UTC, well before the quiet hours
+    let lines = home.simulate(&["--start", "1704096000"]);
+    assert!(lines.iter().any(|l| l.contains("sound critical")));
+}
+
+#[test]
+fn an_out_of_range_time_is_refused_not_a_panic() {
+    let home = Home::new("simulate-overflow", CONFIG);
+    let trace = home.dir.join("overflow.trace");
+    fs::write(&trace, "0 50 D\n99999999999999999d 49 D\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-batt-reminder"))
+        .arg("--simulate")
+        .arg(&trace)
+        .env("HOME", home.dir.path())
+        .env_remove("NOTIFY_SOCKET")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        format!(
+            "simulate: {} : line 2: invalid time '99999999999999999d'",
+            trace.display()
+        )
+    );
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::TempDir;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn the_unit_quotes_a_binary_path_with_spaces() {
+    let dir = TempDir::new("systemd-unit");
+    fs::create_dir_all(dir.join(".config")).unwrap();
+    let bin = dir.join("my bin %h $HOME");
+    fs::create_dir_all(&bin).unwrap();
+    let exe = bin.join("rust-batt-reminder");
+    fs::copy(env!("CARGO_BIN_EXE_rust-batt-reminder"), &exe).unwrap();
+
+    let output = Command::new(&exe)
+        .args(["install-service", "--no-enable"])
+        .env("HOME", dir.path())
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let unit =
+        fs::read_to_string(dir.join("config/systemd/user/rust-batt-reminder.service")).unwrap();
+    let exec_start = format!(
+        "ExecStart=\"{}/my bin %%h $$HOME/rust-batt-reminder\"\n",
+        dir.path().display()
+    );
+    assert!(unit.contains(&exec_start), "{}", unit);
+}
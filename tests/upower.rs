@@ -0,0 +1,280 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::battery::BatterySource;
+use rust_batt_reminder::config::Config;
+use rust_batt_reminder::monitor::BatteryMonitor;
+use rust_batt_reminder::shutdown::Shutdown;
+use rust_batt_reminder::upower::UPowerBattery;
+use rust_batt_reminder::{Event, Level};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::blocking::{connection, Connection};
+
+const DISPLAY_DEVICE: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+// a dbus-daemon of its own for each test, killed on drop
+struct Bus {
+    daemon: process::Child,
+    address: String,
+}
+
+impl Bus {
+    // None when there is no dbus-daemon to run
+    fn start() -> Option<Bus> {
+        let mut daemon = process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(Bus {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+// the display device as UPower would export it
+struct MockDevice {
+    percentage: f64,
+    state: u32,
+    time_to_empty: i64,
+    time_to_full: i64,
+}
+
+#[zbus::interface(name = "org.freedesktop.UPower.Device")]
+impl MockDevice {
+    #[zbus(property)]
+    fn is_present(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn native_path(&self) -> String {
+        "".to_string()
+    }
+
+    #[zbus(property)]
+    fn percentage(&self) -> f64 {
+        self.percentage
+    }
+
+    #[zbus(property)]
+    fn state(&self) -> u32 {
+        self.state
+    }
+
+    #[zbus(property)]
+    fn time_to_empty(&self) -> i64 {
+        self.time_to_empty
+    }
+
+    #[zbus(property)]
+    fn time_to_full(&self) -> i64 {
+        self.time_to_full
+    }
+}
+
+// takes org.freedesktop.UPower on the bus, gone again once dropped
+fn serve(bus: &Bus, device: MockDevice) -> Connection {
+    connection::Builder::address(&bus.address[..])
+        .unwrap()
+        .serve_at(DISPLAY_DEVICE, device)
+        .unwrap()
+        .name("org.freedesktop.UPower")
+        .unwrap()
+        .build()
+        .unwrap()
+}
+
+// changes the device and sends PropertiesChanged like UPower does
+fn set<F: FnOnce(&mut MockDevice)>(upower: &Connection, change: F) {
+    let device = upower
+        .object_server()
+        .interface::<_, MockDevice>(DISPLAY_DEVICE)
+        .unwrap();
+    change(&mut device.get_mut());
+    zbus::block_on(device.get().percentage_changed(device.signal_emitter())).unwrap();
+}
+
+fn discharging(percentage: f64) -> MockDevice {
+    MockDevice {
+        percentage,
+        state: 2,
+        time_to_empty: 7200,
+        time_to_full: 0,
+    }
+}
+
+// a sysfs battery to fall back on
+struct FakeSysfs {
+    root: TempDir,
+}
+
+impl FakeSysfs {
+    fn new(name: &str, capacity: u64, status: &str) -> FakeSysfs {
+        let root = TempDir::new(name);
+        fs::create_dir_all(root.join("BAT0")).unwrap();
+        fs::write(root.join("BAT0/capacity"), format!("{}\n", capacity)).unwrap();
+        fs::write(root.join("BAT0/status"), format!("{}\n", status)).unwrap();
+        FakeSysfs { root }
+    }
+
+    fn configure(&self, configuration: &mut Config) {
+        configuration.path_to_capacity = self.root.join("BAT0/capacity").display().to_string();
+        configuration.path_to_status = self.root.join("BAT0/status").display().to_string();
+    }
+}
+
+// polls `check` until it holds, panics after a few seconds
+fn eventually<F: Fn() -> bool>(check: F) {
+    let start = Instant::now();
+    while !check() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "the battery never changed"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+macro_rules! bus_or_skip {
+    () => {
+        match Bus::start() {
+            Some(b) => b,
+            None => {
+                eprintln!("no dbus-daemon to run, skipping");
+                return;
+            }
+        }
+    };
+}
+
+#[test]
+fn follows_property_changes() {
+    let bus = bus_or_skip!();
+    let upower = serve(&bus, discharging(80.4));
+    let battery = UPowerBattery::with_address(&Config::default_config(), &bus.address).unwrap();
+
+    assert_eq!(battery.capacity(), Ok(80));
+    assert_eq!(battery.status(), Ok("Discharging".to_string()));
+    assert_eq!(battery.dir(), None);
+    let estimate = battery.estimate("Discharging").unwrap();
+    assert_eq!(estimate.seconds, 7200);
+    assert!(!estimate.to_full);
+
+    let changes = battery.changes().unwrap();
+    set(&upower, |d| {
+        d.percentage = 15.0;
+        d.state = 1;
+        d.time_to_empty = 0;
+        d.time_to_full = 0;
+    });
+    changes.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(battery.capacity(), Ok(15));
+    assert_eq!(battery.status(), Ok("Charging".to_string()));
+    // UPower has no estimate yet, the monitor works one out
+    assert!(battery.estimate("Charging").is_none());
+}
+
+#[test]
+fn falls_back_to_sysfs_while_upower_is_away() {
+    let bus = bus_or_skip!();
+    let sysfs = FakeSysfs::new("upower-fallback", 55, "Full");
+    let mut configuration = Config::default_config();
+    sysfs.configure(&mut configuration);
+    let battery = UPowerBattery::with_address(&configuration, &bus.address).unwrap();
+    assert_eq!(battery.capacity(), Ok(55));
+    assert_eq!(battery.status(), Ok("Full".to_string()));
+    assert!(battery.estimate("Full").is_none());
+
+    let upower = serve(&bus, discharging(42.0));
+    eventually(|| battery.capacity() == Ok(42));
+    assert_eq!(battery.status(), Ok("Discharging".to_string()));
+
+    drop(upower);
+    eventually(|| battery.capacity() == Ok(55));
+    assert_eq!(battery.status(), Ok("Full".to_string()));
+}
+
+#[test]
+fn plug_events_come_without_waiting_for_a_poll() {
+    let bus = bus_or_skip!();
+    let upower = serve(&bus, discharging(60.0));
+    let mut configuration = Config::default_config();
+    configuration.health_check_enable = false;
+    configuration.enable_plug_in_check = true;
+    // long enough that only PropertiesChanged can explain a quick event
+    configuration.plug_in_check_interval = 600;
+    let battery = UPowerBattery::with_address(&configuration, &bus.address).unwrap();
+    let mut monitor = BatteryMonitor::new(&configuration).with_source(Arc::new(battery));
+    let events = monitor.subscribe();
+    let shutdown = Shutdown::new();
+    let handle = monitor.start(&shutdown);
+    // the plug loop has taken its first look by the time the level loop did
+    while !matches!(
+        events.recv_timeout(Duration::from_secs(5)).unwrap(),
+        Event::Polled { .. }
+    ) {}
+    thread::sleep(Duration::from_millis(200));
+
+    set(&upower, |d| d.state = 1);
+    let plugged_in = loop {
+        if let Event::PluggedIn(reading) = events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            break reading;
+        }
+    };
+    assert_eq!(plugged_in.status(), "Charging");
+    assert_eq!(plugged_in.capacity(), 60);
+
+    shutdown.trigger();
+    assert!(handle.join());
+}
+
+#[test]
+fn level_changes_come_without_waiting_for_a_poll() {
+    let bus = bus_or_skip!();
+    let upower = serve(&bus, discharging(60.0));
+    let mut configuration = Config::default_config();
+    configuration.health_check_enable = false;
+    configuration.enable_plug_in_check = false;
+    configuration.normal_sleep_time = 600;
+    let battery = UPowerBattery::with_address(&configuration, &bus.address).unwrap();
+    let mut monitor = BatteryMonitor::new(&configuration).with_source(Arc::new(battery));
+    let events = monitor.subscribe();
+    let shutdown = Shutdown::new();
+    let handle = monitor.start(&shutdown);
+    assert!(matches!(
+        events.recv_timeout(Duration::from_secs(5)).unwrap(),
+        Event::Polled { .. }
+    ));
+
+    set(&upower, |d| d.percentage = 12.0);
+    let crossed = loop {
+        if let Event::LevelCrossed { to, reading, .. } =
+            events.recv_timeout(Duration::from_secs(5)).unwrap()
+        {
+            break (to, reading.capacity());
+        }
+    };
+    assert_eq!(crossed, (Level::Critical, 12));
+
+    shutdown.trigger();
+    assert!(handle.join());
+}
@@ -0,0 +1,264 @@
+mod common;
+
+use common::TempDir;
+use rust_batt_reminder::config::Config;
+use rust_batt_reminder::history::Sample;
+use rust_batt_reminder::webhooks::{sign, Webhooks};
+use rust_batt_reminder::{Event, Level, Reading};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+struct Request {
+    headers: Vec<String>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<String> {
+        self.headers.iter().find_map(|h| {
+            let (k, v) = h.split_once(':')?;
+            match k.eq_ignore_ascii_case(name) {
+                true => Some(v.trim().to_string()),
+                false => None,
+            }
+        })
+    }
+}
+
+// a local HTTP stand-in answering with `statuses` in turn, then 200
+fn stand_in(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut statuses = statuses.into_iter();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_string());
+            }
+            let request = Request {
+                headers,
+                body: String::new(),
+            };
+            let length: usize = request
+                .header("Content-Length")
+                .and_then(|l| l.parse().ok())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let status = statuses.next().unwrap_or(200);
+            let _ = stream.write_all(
+                format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .as_bytes(),
+            );
+            let _ = sender.send(Request {
+                body: String::from_utf8(body).unwrap(),
+                ..request
+            });
+        }
+    });
+    (url, receiver)
+}
+
+fn queue_file(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!(
+        "batt_reminder-test-webhook-{}-{}.json",
+        name,
+        process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn webhook_config(url: &str) -> Config {
+    let mut configuration = Config::default_config();
+    configuration.webhook_urls = vec![url.to_string()];
+    configuration.webhook_backoff = 1;
+    configuration.webhook_timeout = 5;
+    configuration
+}
+
+fn reading(capacity: u64, status: &str) -> Reading {
+    Reading {
+        sample: Sample::read(None, 1_700_000_000, capacity, status),
+        estimate: None,
+    }
+}
+
+fn next(requests: &Receiver<Request>) -> Request {
+    requests.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+#[test]
+fn delivers_signed_payloads() {
+    let (url, requests) = stand_in(vec![]);
+    let mut configuration = webhook_config(&url);
+    configuration.webhook_secret = "s3cret".to_string();
+    configuration.webhook_headers = vec!["X-Token: abc".to_string()];
+    let path = queue_file("signed");
+    let mut webhooks = Webhooks::with_queue(&configuration, path.clone());
+
+    webhooks.update(&Event::LevelCrossed {
+        from: Level::Normal,
+        to: Level::Low,
+        reading: reading(44, "Discharging"),
+    });
+    let request = next(&requests);
+    assert_eq!(request.header("X-Token").as_deref(), Some("abc"));
+    assert_eq!(
+        request.header("X-Batt-Reminder-Signature"),
+        Some(sign("s3cret", &request.body))
+    );
+    let payload: Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(payload["event"], "low");
+    assert_eq!(payload["from"], "normal");
+    assert_eq!(payload["capacity"], 44);
+    assert_eq!(payload["status"], "Discharging");
+
+    // polls and going back to normal are not sent
+    webhooks.update(&Event::LevelCrossed {
+        from: Level::Low,
+        to: Level::Normal,
+        reading: reading(60, "Charging"),
+    });
+    webhooks.update(&Event::PluggedIn(reading(60, "Charging")));
+    let payload: Value = serde_json::from_str(&next(&requests).body).unwrap();
+    assert_eq!(payload["event"], "plug_in");
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn retries_until_the_target_is_back() {
+    let (url, requests) = stand_in(vec![503, 500]);
+    let path = queue_file("retry");
+    let mut webhooks = Webhooks::with_queue(&webhook_config(&url), path.clone());
+
+    webhooks.update(&Event::ReadError("no battery".to_string()));
+    // reported once until the battery reads again
+    webhooks.update(&Event::ReadError("no battery".to_string()));
+    let first = next(&requests).body;
+    assert_eq!(next(&requests).body, first);
+    assert_eq!(next(&requests).body, first);
+    let payload: Value = serde_json::from_str(&first).unwrap();
+    assert_eq!(payload["event"], "read_error");
+    assert_eq!(payload["error"], "no battery");
+    assert!(requests.recv_timeout(Duration::from_secs(2)).is_err());
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn offline_queue_is_bounded_and_kept_on_disk() {
+    // nothing listens on a port we bound and closed again
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut configuration = webhook_config(&format!("http://127.0.0.1:{}/hook", port));
+    configuration.webhook_queue_size = 2;
+    configuration.webhook_backoff = 60;
+    let path = queue_file("bounded");
+    let mut webhooks = Webhooks::with_queue(&configuration, path.clone());
+
+    webhooks.update(&Event::PluggedIn(reading(50, "Charging")));
+    webhooks.update(&Event::PluggedOut(reading(51, "Discharging")));
+    webhooks.update(&Event::PluggedIn(reading(52, "Charging")));
+    thread::sleep(Duration::from_millis(500));
+    let queue: Vec<Value> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    let capacities: Vec<u64> = queue
+        .iter()
+        .map(|d| {
+            let body: Value = serde_json::from_str(d["body"].as_str().unwrap()).unwrap();
+            body["capacity"].as_u64().unwrap()
+        })
+        .collect();
+    assert_eq!(capacities, vec![51, 52]);
+    let _ = fs::remove_file(path);
+}
+
+#[test]
+fn credentials_stay_off_the_command_line() {
+    // a curl that notes its arguments and config file before running the
+    // real one
+    let dir = TempDir::new("curl");
+    let real = process::Command::new("sh")
+        .args(["-c", "command -v curl"])
+        .output()
+        .unwrap();
+    let real = String::from_utf8(real.stdout).unwrap().trim().to_string();
+    let wrapper = dir.join("curl");
+    fs::write(
+        &wrapper,
+        format!(
+            "#!/bin/sh\n\
+             printf '%s\\n' \"$@\" >> '{dir}/argv'\n\
+             prev=\n\
+             for arg in \"$@\"; do\n\
+             [ \"$prev\" = --config ] && cat \"$arg\" >> '{dir}/config'\n\
+             prev=$arg\n\
+             done\n\
+             exec '{real}' \"$@\"\n",
+            dir = dir.path().display(),
+            real = real
+        ),
+    )
+    .unwrap();
+    process::Command::new("chmod")
+        .arg("755")
+        .arg(&wrapper)
+        .status()
+        .unwrap();
+
+    let (url, requests) = stand_in(vec![]);
+    let mut configuration = webhook_config(&url);
+    configuration.webhook_secret = "n0t-on-argv".to_string();
+    configuration.webhook_headers = vec!["Authorization: Bearer t0ken-\"x\"".to_string()];
+    let path = queue_file("argv");
+    let mut webhooks = Webhooks::with_curl(&configuration, path.clone(), &wrapper);
+    webhooks.update(&Event::PluggedIn(reading(60, "Charging")));
+    let request = next(&requests);
+    assert_eq!(
+        request.header("Authorization").as_deref(),
+        Some("Bearer t0ken-\"x\"")
+    );
+    assert_eq!(
+        request.header("X-Batt-Reminder-Signature"),
+        Some(sign("n0t-on-argv", &request.body))
+    );
+
+    let argv = fs::read_to_string(dir.join("argv")).unwrap();
+    assert!(!argv.contains("t0ken"));
+    assert!(!argv.contains("sha256="));
+    let config = fs::read_to_string(dir.join("config")).unwrap();
+    assert!(config.contains("t0ken"));
+    assert!(config.contains("X-Batt-Reminder-Signature"));
+    // the config file is gone once curl is done
+    thread::sleep(Duration::from_millis(200));
+    let config_path = argv
+        .lines()
+        .skip_while(|a| *a != "--config")
+        .nth(1)
+        .unwrap();
+    assert!(!std::path::Path::new(config_path).exists());
+    let _ = fs::remove_file(path);
+}